@@ -1,6 +1,11 @@
 use clap_complete::{generate_to, Shell};
 use std::fs;
 
+// the cli refers to the watch command's output format
+mod watch {
+    include!("src/watch/format.rs");
+}
+
 include!("src/cli.rs");
 
 fn manpages(binary_name: &str) {
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::notification::Notification;
+use crate::{
+    find_lowest_threshold, get_current_power, get_status_charging, read_attribute, Battery,
+    ChargingStatus, Error, DEFAULT_BATTERY,
+};
+
+pub const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+pub const THERMAL_DIR: &str = "/sys/class/thermal";
+
+/// Directory containing the attributes of the battery, e.g. /sys/class/power_supply/BAT0
+pub fn get_battery_dir(battery: Option<&Battery>) -> PathBuf {
+    let battery = battery.unwrap_or(DEFAULT_BATTERY);
+    PathBuf::from(POWER_SUPPLY_DIR).join(battery)
+}
+
+/// Coarse level reported in capacity_level by batteries without a numeric capacity, thresholds can
+/// be given by these names as well
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapacityLevel {
+    Critical,
+    Low,
+    Normal,
+    High,
+    Full,
+}
+
+impl CapacityLevel {
+    /// The capacity level of the name written by the kernel, None for 'Unknown'
+    pub fn parse(name: &str) -> Option<CapacityLevel> {
+        match name {
+            "Critical" => Some(CapacityLevel::Critical),
+            "Low" => Some(CapacityLevel::Low),
+            "Normal" => Some(CapacityLevel::Normal),
+            "High" => Some(CapacityLevel::High),
+            "Full" => Some(CapacityLevel::Full),
+            _ => None,
+        }
+    }
+
+    /// The capacity level of a name in the config, unlike parse the case does not matter
+    pub fn from_name(name: &str) -> Option<CapacityLevel> {
+        ["Critical", "Low", "Normal", "High", "Full"]
+            .into_iter()
+            .find(|known| known.eq_ignore_ascii_case(name.trim()))
+            .and_then(CapacityLevel::parse)
+    }
+
+    /// Battery-level in percent standing in for the capacity level
+    pub fn percent(self) -> u32 {
+        match self {
+            CapacityLevel::Critical => 5,
+            CapacityLevel::Low => 15,
+            CapacityLevel::Normal => 50,
+            CapacityLevel::High => 80,
+            CapacityLevel::Full => 100,
+        }
+    }
+}
+
+/// Battery-level in percent from the attributes in dir. Batteries without a numeric capacity fall
+/// back to energy_now/energy_full, then charge_now/charge_full and finally capacity_level.
+pub fn read_level(dir: &Path) -> Result<u32, Error> {
+    let path = dir.join("capacity");
+    let capacity = read_attribute(&path).and_then(|contents| {
+        contents.parse().map_err(|_| Error::ParseAttribute {
+            path: path.clone(),
+            contents,
+        })
+    });
+    let Err(err) = capacity else {
+        return capacity;
+    };
+
+    let read = |name: &str| read_attribute(&dir.join(name)).ok()?.parse::<u64>().ok();
+    let ratio = |now: &str, full: &str| {
+        let full = read(full).filter(|&full| full > 0)?;
+        Some(read(now)? as f64 / full as f64)
+    };
+    let ratio = ratio("energy_now", "energy_full").or_else(|| ratio("charge_now", "charge_full"));
+    if let Some(ratio) = ratio {
+        return Ok((ratio * 100.0).round().min(100.0) as u32);
+    }
+    read_attribute(&dir.join("capacity_level"))
+        .ok()
+        .and_then(|name| CapacityLevel::parse(&name))
+        .map(CapacityLevel::percent)
+        .ok_or(err)
+}
+
+/// True if an AC adapter or USB power supply is online, None if the system has neither
+pub fn is_ac_online() -> Option<bool> {
+    let mut ac_online = None;
+    for entry in std::fs::read_dir(POWER_SUPPLY_DIR).ok()?.flatten() {
+        let dir = entry.path();
+        let supply_type = read_attribute(&dir.join("type")).unwrap_or_default();
+        if supply_type != "Mains" && supply_type != "USB" {
+            continue;
+        }
+        let online = read_attribute(&dir.join("online")).is_ok_and(|online| online == "1");
+        ac_online = Some(ac_online.unwrap_or(false) || online);
+    }
+    ac_online
+}
+
+/// Power an online USB power supply such as a USB-C charger can deliver in W, calculated from its
+/// input current limit and voltage. The highest one if several are online.
+pub fn get_usb_supply_watts() -> Option<f64> {
+    let mut watts: Option<f64> = None;
+    for entry in std::fs::read_dir(POWER_SUPPLY_DIR).ok()?.flatten() {
+        let dir = entry.path();
+        let read = |name: &str| read_attribute(&dir.join(name)).ok();
+        if read("type").as_deref() != Some("USB") || read("online").as_deref() != Some("1") {
+            continue;
+        }
+        let read_u64 = |name: &str| read(name)?.parse::<u64>().ok();
+        let Some(current_limit) = read_u64("input_current_limit") else {
+            continue;
+        };
+        let Some(voltage) = read_u64("voltage_now").or_else(|| read_u64("voltage_max")) else {
+            continue;
+        };
+        let supply_watts = current_limit as f64 * voltage as f64 / 1e12;
+        watts = Some(watts.map_or(supply_watts, |watts| watts.max(supply_watts)));
+    }
+    watts
+}
+
+/// Read a numeric attribute from the battery's dir, None if the battery does not provide it
+pub fn read_u64(dir: &Path, name: &str) -> Option<u64> {
+    read_attribute(&dir.join(name)).ok()?.parse().ok()
+}
+
+/// Charging status from the status attribute in the battery's dir
+pub fn read_status(dir: &Path) -> Result<ChargingStatus, Error> {
+    let status = match read_attribute(&dir.join("status"))?.as_str() {
+        "Charging" => ChargingStatus::Charging,
+        "Discharging" => ChargingStatus::Discharging,
+        "Full" => ChargingStatus::Full,
+        "Not charging" => ChargingStatus::NotCharging,
+        _ => ChargingStatus::Unknown,
+    };
+    Ok(status)
+}
+
+/// Estimated time until the battery is empty when discharging or full when charging. Uses the
+/// energy attributes (µWh, µW) and falls back to the charge attributes (µAh, µA).
+pub fn get_time_remaining(dir: &Path, charging: bool) -> Option<Duration> {
+    let (now, full, rate) = match read_u64(dir, "energy_now") {
+        Some(energy_now) => (
+            energy_now,
+            read_u64(dir, "energy_full"),
+            read_u64(dir, "power_now")?,
+        ),
+        None => (
+            read_u64(dir, "charge_now")?,
+            read_u64(dir, "charge_full"),
+            read_u64(dir, "current_now")?,
+        ),
+    };
+    if rate == 0 {
+        return None;
+    }
+
+    let remaining = if charging {
+        full?.saturating_sub(now)
+    } else {
+        now
+    };
+    let hours = remaining as f64 / rate as f64;
+    Some(Duration::from_secs_f64(hours * 3600.0))
+}
+
+/// Level at which the battery stops charging if a charge limit below 100% is configured
+pub fn get_charge_limit(dir: &Path) -> Option<u32> {
+    // older kernels name the attribute charge_stop_threshold
+    let limit = read_u64(dir, "charge_control_end_threshold")
+        .or_else(|| read_u64(dir, "charge_stop_threshold"))?;
+    u32::try_from(limit).ok().filter(|&limit| limit < 100)
+}
+
+/// Everything known about the battery at one point in time, values the battery does not provide
+/// are None
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub level: u32,
+    pub status: ChargingStatus,
+    pub battery: String,
+    // remaining energy in Wh
+    pub energy_wh: Option<f64>,
+    // current power draw in W
+    pub power_w: Option<f64>,
+    pub time_remaining_secs: Option<u64>,
+    // full capacity relative to the design capacity in percent
+    pub health_percent: Option<u32>,
+    pub temperature_c: Option<f64>,
+    // lowest threshold at or above the current level
+    pub active_threshold: Option<u32>,
+}
+
+/// Read the level and status of the battery together with everything else in a Snapshot, the
+/// active threshold is looked up in the given notifications by level
+pub fn get_snapshot(
+    battery: Option<&Battery>,
+    thresholds: &HashMap<u32, Notification>,
+) -> Result<Snapshot, Error> {
+    let level = get_current_power(battery)?;
+    let status = get_status_charging(battery)?;
+    let active_threshold = find_lowest_threshold(level, thresholds);
+    Ok(read_snapshot(
+        &get_battery_dir(battery),
+        level,
+        status,
+        active_threshold,
+    ))
+}
+
+/// Complete a Snapshot of the battery in dir for the already known level and status
+pub fn read_snapshot(
+    dir: &Path,
+    level: u32,
+    status: ChargingStatus,
+    active_threshold: Option<u32>,
+) -> Snapshot {
+    let charging = status == ChargingStatus::Charging;
+    Snapshot {
+        level,
+        battery: dir.file_name().unwrap_or_default().to_string_lossy().into(),
+        energy_wh: get_energy_wh(dir),
+        power_w: get_power_w(dir),
+        time_remaining_secs: get_time_remaining(dir, charging).map(|time| time.as_secs()),
+        health_percent: get_health_percent(dir),
+        temperature_c: get_temperature_c(dir),
+        active_threshold,
+        status,
+    }
+}
+
+/// Temperature of the battery in °C from its temp attribute in tenths of a degree, or from a
+/// thermal zone of the type 'battery' or named after the battery if it has none
+pub fn get_temperature_c(dir: &Path) -> Option<f64> {
+    if let Some(decidegrees) = read_i64(&dir.join("temp")) {
+        return Some(decidegrees as f64 / 10.0);
+    }
+
+    read_thermal_zone_c(Path::new(THERMAL_DIR), dir.file_name()?.to_str()?)
+}
+
+/// Temperature in °C of the first zone in thermal_dir of the type 'battery' or named after the
+/// battery, zones report millidegrees
+fn read_thermal_zone_c(thermal_dir: &Path, battery: &Battery) -> Option<f64> {
+    for entry in std::fs::read_dir(thermal_dir).ok()?.flatten() {
+        let dir = entry.path();
+        let zone_type = read_attribute(&dir.join("type")).unwrap_or_default();
+        if !zone_type.eq_ignore_ascii_case("battery") && !zone_type.eq_ignore_ascii_case(battery) {
+            continue;
+        }
+        if let Some(millidegrees) = read_i64(&dir.join("temp")) {
+            return Some(millidegrees as f64 / 1000.0);
+        }
+    }
+    None
+}
+
+fn read_i64(path: &Path) -> Option<i64> {
+    read_attribute(path).ok()?.parse().ok()
+}
+
+/// Remaining energy in Wh, calculated from the charge and voltage if the battery does not report
+/// its energy
+pub fn get_energy_wh(dir: &Path) -> Option<f64> {
+    let micro_wh = match read_u64(dir, "energy_now") {
+        Some(energy_now) => energy_now as f64,
+        None => {
+            let charge_now = read_u64(dir, "charge_now")? as f64;
+            charge_now * read_u64(dir, "voltage_now")? as f64 / 1e6
+        }
+    };
+    Some(micro_wh / 1e6)
+}
+
+/// Current power draw in W, calculated from the current and voltage if the battery does not report
+/// its power
+pub fn get_power_w(dir: &Path) -> Option<f64> {
+    let micro_w = match read_u64(dir, "power_now") {
+        Some(power_now) => power_now as f64,
+        None => {
+            let current_now = read_u64(dir, "current_now")? as f64;
+            current_now * read_u64(dir, "voltage_now")? as f64 / 1e6
+        }
+    };
+    Some(micro_w / 1e6)
+}
+
+/// Wear and identification of the battery, values the battery does not provide are None
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Health {
+    pub battery: String,
+    pub manufacturer: Option<String>,
+    pub model_name: Option<String>,
+    pub technology: Option<String>,
+    pub serial_number: Option<String>,
+    // capacity when fully charged and the capacity the battery was designed for in Wh
+    pub energy_full_wh: Option<f64>,
+    pub energy_full_design_wh: Option<f64>,
+    pub health_percent: Option<u32>,
+    // capacity lost relative to the design capacity in percent
+    pub wear_percent: Option<u32>,
+    pub cycle_count: Option<u64>,
+}
+
+/// Read the health of the battery, fails if the battery does not exist
+pub fn get_health(battery: Option<&Battery>) -> Result<Health, Error> {
+    let dir = get_battery_dir(battery);
+    // only used to report a missing battery, everything else is optional
+    read_attribute(&dir.join("type"))?;
+
+    let read_string = |name: &str| {
+        read_attribute(&dir.join(name))
+            .ok()
+            .filter(|value| !value.is_empty())
+    };
+    let health_percent = get_health_percent(&dir);
+    Ok(Health {
+        battery: battery.unwrap_or(DEFAULT_BATTERY).to_string(),
+        manufacturer: read_string("manufacturer"),
+        model_name: read_string("model_name"),
+        technology: read_string("technology"),
+        serial_number: read_string("serial_number"),
+        energy_full_wh: read_wh(&dir, "energy_full", "charge_full"),
+        energy_full_design_wh: read_wh(&dir, "energy_full_design", "charge_full_design"),
+        health_percent,
+        wear_percent: health_percent.map(|health| 100u32.saturating_sub(health)),
+        // batteries without a cycle counter report 0
+        cycle_count: read_u64(&dir, "cycle_count").filter(|&cycles| cycles > 0),
+    })
+}
+
+/// Read an energy attribute in Wh, calculated from the charge attribute and the design voltage if
+/// the battery only reports charges
+fn read_wh(dir: &Path, energy: &str, charge: &str) -> Option<f64> {
+    let micro_wh = match read_u64(dir, energy) {
+        Some(energy) => energy as f64,
+        None => {
+            let charge = read_u64(dir, charge)? as f64;
+            charge * read_u64(dir, "voltage_min_design")? as f64 / 1e6
+        }
+    };
+    Some(micro_wh / 1e6)
+}
+
+/// Full capacity relative to the design capacity in percent
+pub fn get_health_percent(dir: &Path) -> Option<u32> {
+    let (full, design) = match read_u64(dir, "energy_full") {
+        Some(energy_full) => (energy_full, read_u64(dir, "energy_full_design")?),
+        None => (
+            read_u64(dir, "charge_full")?,
+            read_u64(dir, "charge_full_design")?,
+        ),
+    };
+    if design == 0 {
+        return None;
+    }
+    Some((full as f64 / design as f64 * 100.0).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn level_falls_back_to_energy_and_capacity_level() {
+        let dir = TestDir::new("level");
+        std::fs::write(dir.join("capacity_level"), "Low\n").unwrap();
+        assert_eq!(read_level(&dir).unwrap(), 15);
+
+        std::fs::write(dir.join("energy_now"), "21000000\n").unwrap();
+        std::fs::write(dir.join("energy_full"), "50000000\n").unwrap();
+        assert_eq!(read_level(&dir).unwrap(), 42);
+
+        std::fs::write(dir.join("capacity"), "57\n").unwrap();
+        assert_eq!(read_level(&dir).unwrap(), 57);
+        let path = dir.to_path_buf();
+        drop(dir);
+        assert!(read_level(&path).is_err());
+    }
+
+    #[test]
+    fn temperature_falls_back_to_thermal_zone() {
+        let dir = TestDir::new("thermal");
+        let zone = |name: &str, zone_type: &str, millidegrees: &str| {
+            let zone = dir.join(name);
+            std::fs::create_dir_all(&zone).unwrap();
+            std::fs::write(zone.join("type"), format!("{}\n", zone_type)).unwrap();
+            std::fs::write(zone.join("temp"), format!("{}\n", millidegrees)).unwrap();
+        };
+        zone("thermal_zone0", "x86_pkg_temp", "61000");
+        zone("thermal_zone1", "BAT1", "31500");
+        assert_eq!(read_thermal_zone_c(&dir, "BAT1"), Some(31.5));
+        assert_eq!(read_thermal_zone_c(&dir, "BAT0"), None);
+
+        zone("thermal_zone2", "battery", "29000");
+        assert_eq!(read_thermal_zone_c(&dir, "BAT0"), Some(29.0));
+    }
+}
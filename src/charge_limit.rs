@@ -0,0 +1,249 @@
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::battery::get_battery_dir;
+use crate::{read_attribute, Battery, Error};
+
+const START_ATTRIBUTE: &str = "charge_control_start_threshold";
+const END_ATTRIBUTE: &str = "charge_control_end_threshold";
+
+/// Levels between which the firmware keeps the battery, charging starts below start and stops at
+/// end. Not every battery supports a start threshold.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChargeLimit {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<u32>,
+    pub end: u32,
+}
+
+impl ChargeLimit {
+    fn validate(&self) -> Result<(), Error> {
+        let valid =
+            self.end > 0 && self.end <= 100 && self.start.is_none_or(|start| start < self.end);
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::ChargeLimit(format!(
+                "invalid charge limit {}, the start has to be below the end and the end at most 100",
+                self
+            )))
+        }
+    }
+}
+
+impl std::fmt::Display for ChargeLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.start {
+            Some(start) => write!(f, "{}%-{}%", start, self.end),
+            None => write!(f, "{}%", self.end),
+        }
+    }
+}
+
+/// Read the charge limit of the battery
+pub fn get(battery: Option<&Battery>) -> Result<ChargeLimit, Error> {
+    read_limit(&get_battery_dir(battery))
+}
+
+/// Set the charge limit of the battery. If the attributes are not writable for the current user
+/// the limit is set through 'pkexec powernotd charge-limit set' instead.
+pub fn set(battery: Option<&Battery>, limit: &ChargeLimit) -> Result<(), Error> {
+    limit.validate()?;
+    match write_limit(&get_battery_dir(battery), limit) {
+        Err(Error::WriteAttribute { source, .. })
+            if source.kind() == ErrorKind::PermissionDenied =>
+        {
+            set_privileged(battery, limit)
+        }
+        result => result,
+    }
+}
+
+/// Set the charge limit in the battery directory again if it differs from the configured one, the
+/// firmware of some laptops resets it on boot or resume. Unlike set it never asks for
+/// authentication, see check_writable.
+pub fn reapply(dir: &Path, limit: &ChargeLimit) -> Result<bool, Error> {
+    if read_limit(dir).is_ok_and(|current| current == *limit) {
+        return Ok(false);
+    }
+    write_limit(dir, limit)?;
+    Ok(true)
+}
+
+/// Fails unless the current user may write the end threshold, which is the case for root or if its
+/// permissions were changed e.g. by a udev rule. Nothing is written.
+pub fn check_writable(dir: &Path) -> Result<(), Error> {
+    let path = dir.join(END_ATTRIBUTE);
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .map(|_| ())
+        .map_err(|source| Error::WriteAttribute { path, source })
+}
+
+fn set_privileged(battery: Option<&Battery>, limit: &ChargeLimit) -> Result<(), Error> {
+    let command_error = |reason: String| Error::Command {
+        command: "pkexec powernotd charge-limit set".to_string(),
+        reason,
+    };
+    let exe = std::env::current_exe().map_err(|err| command_error(err.to_string()))?;
+    let mut command = Command::new("pkexec");
+    command.arg(exe);
+    if let Some(battery) = battery {
+        command.arg("--battery").arg(battery);
+    }
+    command.arg("charge-limit").arg("set");
+    if let Some(start) = limit.start {
+        command.arg(start.to_string());
+    }
+    command.arg(limit.end.to_string());
+
+    let status = command
+        .status()
+        .map_err(|err| command_error(err.to_string()))?;
+    if !status.success() {
+        return Err(command_error(format!("exited with {}", status)));
+    }
+    Ok(())
+}
+
+/// Read the charge limit from the attributes in the battery directory
+pub fn read_limit(dir: &Path) -> Result<ChargeLimit, Error> {
+    let start_path = dir.join(START_ATTRIBUTE);
+    let start = if start_path.exists() {
+        Some(read_level(&start_path)?)
+    } else {
+        None
+    };
+    let end = read_level(&dir.join(END_ATTRIBUTE))?;
+    Ok(ChargeLimit { start, end })
+}
+
+/// Write the charge limit to the attributes in the battery directory and verify that the firmware
+/// accepted it. If a write or the verification fails the previous limit is restored.
+pub fn write_limit(dir: &Path, limit: &ChargeLimit) -> Result<(), Error> {
+    limit.validate()?;
+    let end_path = dir.join(END_ATTRIBUTE);
+    if !end_path.exists() {
+        return Err(Error::ChargeLimit(format!(
+            "{} does not support a charge limit",
+            dir.to_string_lossy()
+        )));
+    }
+    if limit.start.is_some() && !dir.join(START_ATTRIBUTE).exists() {
+        return Err(Error::ChargeLimit(format!(
+            "{} does not support a start threshold",
+            dir.to_string_lossy()
+        )));
+    }
+
+    let previous = read_limit(dir)?;
+    let result = write_levels(dir, limit).and_then(|()| verify_limit(dir, limit));
+    if result.is_err() {
+        if let Err(err) = write_levels(dir, &previous) {
+            eprintln!("Could not restore the charge limit {}: {}", previous, err);
+        }
+    }
+    result
+}
+
+fn write_levels(dir: &Path, limit: &ChargeLimit) -> Result<(), Error> {
+    let start_path = dir.join(START_ATTRIBUTE);
+    let end_path = dir.join(END_ATTRIBUTE);
+    // the firmware rejects a start above the end, so the order depends on the current end
+    let current_end = read_level(&end_path)?;
+    let start_first = limit.end < current_end;
+    if let (true, Some(start)) = (start_first, limit.start) {
+        write_level(&start_path, start)?;
+    }
+    write_level(&end_path, limit.end)?;
+    if let (false, Some(start)) = (start_first, limit.start) {
+        write_level(&start_path, start)?;
+    }
+    Ok(())
+}
+
+fn verify_limit(dir: &Path, limit: &ChargeLimit) -> Result<(), Error> {
+    let applied = read_limit(dir)?;
+    let accepted =
+        applied.end == limit.end && limit.start.is_none_or(|_| applied.start == limit.start);
+    if !accepted {
+        return Err(Error::ChargeLimit(format!(
+            "the firmware did not accept {}, the limit is {}",
+            limit, applied
+        )));
+    }
+    Ok(())
+}
+
+fn read_level(path: &Path) -> Result<u32, Error> {
+    let contents = read_attribute(path)?;
+    contents.parse().map_err(|_| Error::ParseAttribute {
+        path: path.to_owned(),
+        contents,
+    })
+}
+
+fn write_level(path: &Path, level: u32) -> Result<(), Error> {
+    std::fs::write(path, level.to_string()).map_err(|source| Error::WriteAttribute {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn write_limit_to_fake_sysfs() {
+        let dir = TestDir::new("charge-limit");
+        std::fs::write(dir.join(START_ATTRIBUTE), "95\n").unwrap();
+        std::fs::write(dir.join(END_ATTRIBUTE), "100\n").unwrap();
+
+        let limit = ChargeLimit {
+            start: Some(60),
+            end: 80,
+        };
+        write_limit(&dir, &limit).unwrap();
+        assert_eq!(read_limit(&dir).unwrap(), limit);
+
+        let invalid = ChargeLimit {
+            start: Some(90),
+            end: 80,
+        };
+        assert!(write_limit(&dir, &invalid).is_err());
+
+        // the new end is below the current start
+        let lower = ChargeLimit {
+            start: Some(40),
+            end: 50,
+        };
+        write_limit(&dir, &lower).unwrap();
+        assert_eq!(read_limit(&dir).unwrap(), lower);
+    }
+
+    #[test]
+    fn rejected_limit_is_rolled_back() {
+        let dir = TestDir::new("rollback");
+        std::fs::write(dir.join(END_ATTRIBUTE), "100\n").unwrap();
+        // a firmware that ties the start to the end never accepts a start below the end
+        std::os::unix::fs::symlink(END_ATTRIBUTE, dir.join(START_ATTRIBUTE)).unwrap();
+        let previous = read_limit(&dir).unwrap();
+
+        let limit = ChargeLimit {
+            start: Some(60),
+            end: 80,
+        };
+        assert!(matches!(
+            write_limit(&dir, &limit),
+            Err(Error::ChargeLimit(_))
+        ));
+        assert_eq!(read_limit(&dir).unwrap(), previous);
+    }
+}
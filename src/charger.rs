@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::notification::Urgency;
+use crate::ChargingStatus;
+
+/// Level above which charging slows down on its own, slow charging is only reported below it
+const TAPER_LEVEL: u32 = 80;
+
+/// Warning about a charger that cannot keep up, e.g. a weak USB-C charger or a bad cable
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChargerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // warn when the battery gains less than this many percent per hour while charging
+    #[serde(default = "default_min_percent_per_hour")]
+    pub min_percent_per_hour: f64,
+    // warn when an online USB power supply provides less than this many watts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_watts: Option<f64>,
+    // seconds AC has to be connected before the charge rate is judged
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    #[serde(default)]
+    pub urgency: Urgency,
+}
+
+fn default_min_percent_per_hour() -> f64 {
+    5.0
+}
+
+fn default_window_secs() -> u64 {
+    10 * 60
+}
+
+impl Default for ChargerConfig {
+    fn default() -> Self {
+        ChargerConfig {
+            enabled: false,
+            min_percent_per_hour: default_min_percent_per_hour(),
+            min_watts: None,
+            window_secs: default_window_secs(),
+            urgency: Urgency::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChargerSample {
+    timestamp: u64,
+    level: u32,
+    status: ChargingStatus,
+}
+
+/// Samples since AC was connected, the charger is reported at most once per connection
+#[derive(Debug, Default)]
+pub struct ChargerMonitor {
+    samples: VecDeque<ChargerSample>,
+    notified: bool,
+}
+
+impl ChargerMonitor {
+    /// Add a sample and return the warning message if the charger is insufficient. ac_online is
+    /// None if the system has no AC adapter, supply_watts the power of an online USB supply.
+    pub fn record(
+        &mut self,
+        config: &ChargerConfig,
+        timestamp: u64,
+        level: u32,
+        status: ChargingStatus,
+        ac_online: Option<bool>,
+        supply_watts: Option<f64>,
+    ) -> Option<String> {
+        if ac_online != Some(true) {
+            self.samples.clear();
+            self.notified = false;
+            return None;
+        }
+        if self.notified {
+            return None;
+        }
+
+        let weak_supply = supply_watts
+            .zip(config.min_watts)
+            .filter(|(watts, min)| watts < min);
+        if let Some((watts, _)) = weak_supply {
+            self.notified = true;
+            return Some(format!("The USB power supply provides only {:.1} W", watts));
+        }
+
+        let window_start = timestamp.saturating_sub(config.window_secs);
+        self.samples.push_back(ChargerSample {
+            timestamp,
+            level,
+            status,
+        });
+        // keep one sample at or before the start so the whole window is covered
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|sample| sample.timestamp <= window_start)
+        {
+            self.samples.pop_front();
+        }
+        let oldest = self.samples.front()?;
+        let elapsed = timestamp.saturating_sub(oldest.timestamp);
+        if elapsed == 0 || elapsed < config.window_secs {
+            return None;
+        }
+
+        let percent_per_hour = (level as f64 - oldest.level as f64) / (elapsed as f64 / 3600.0);
+        let all =
+            |status: ChargingStatus| self.samples.iter().all(|sample| sample.status == status);
+        let message = if all(ChargingStatus::Discharging) {
+            format!(
+                "AC is connected but the battery is discharging at {:.1}%/h",
+                -percent_per_hour
+            )
+        } else if all(ChargingStatus::Charging)
+            && level < TAPER_LEVEL
+            && percent_per_hour < config.min_percent_per_hour
+        {
+            format!("Charging at only {:.1}%/h", percent_per_hour)
+        } else {
+            return None;
+        };
+        self.notified = true;
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discharging_on_ac_is_reported_once() {
+        let config = ChargerConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let mut monitor = ChargerMonitor::default();
+        let discharging = ChargingStatus::Discharging;
+        assert_eq!(
+            monitor.record(&config, 0, 50, discharging, Some(true), None),
+            None
+        );
+        assert_eq!(
+            monitor
+                .record(&config, 600, 49, discharging, Some(true), None)
+                .as_deref(),
+            Some("AC is connected but the battery is discharging at 6.0%/h")
+        );
+        assert_eq!(
+            monitor.record(&config, 1200, 48, discharging, Some(true), None),
+            None
+        );
+        // connecting again starts over
+        assert_eq!(
+            monitor.record(&config, 1260, 48, discharging, Some(false), None),
+            None
+        );
+        let weak = ChargerConfig {
+            min_watts: Some(30.0),
+            ..config
+        };
+        assert_eq!(
+            monitor
+                .record(
+                    &weak,
+                    1320,
+                    48,
+                    ChargingStatus::Charging,
+                    Some(true),
+                    Some(15.0)
+                )
+                .as_deref(),
+            Some("The USB power supply provides only 15.0 W")
+        );
+    }
+}
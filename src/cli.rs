@@ -1,4 +1,4 @@
-use clap::{CommandFactory, Parser};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 /// Battery-level notification daemon for linux that sends events according to the 'Desktop Notification Specification' to
 /// the user. Notifications are emitted when specific battery-level thresholds are reached or when the
 /// battery is fully charged.
@@ -10,12 +10,13 @@ pub struct Args {
     #[arg(short = 's', long, default_value_t = false)]
     pub status_level: bool,
 
-    /// Print charging status 'charging', 'discharging', 'full' or 'unknown' to stdout then exit
+    /// Print charging status 'charging', 'discharging', 'full', 'not_charging' or 'unknown' to stdout
+    /// then exit
     #[arg(short = 'c', long, default_value_t = false)]
     pub charging_state: bool,
 
     /// Set config-file path if needed, otherwise $XDG_CONFIG_HOME/powernotd/config.json is used
-    #[arg(short = 'f', long)]
+    #[arg(short = 'f', long, global = true)]
     pub config_file: Option<String>,
 
     /// Send desktop notification with current battery-level then exit
@@ -30,10 +31,105 @@ pub struct Args {
     #[arg(short = 'p', long, default_value_t = false)]
     pub show_config_path: bool,
 
+    /// Print a json snapshot with level, status, battery, energy, power draw, time remaining,
+    /// health and active threshold to stdout then exit
+    #[arg(long, default_value_t = false, conflicts_with = "format")]
+    pub json: bool,
+
+    /// Print the battery status in the given format then exit, e.g. '{level}% {status}
+    /// {time_remaining}'. The placeholders are the same as in notification titles and messages.
+    #[arg(long)]
+    pub format: Option<String>,
+
     /// Pass the battery such as 'BAT1' if your system has multiple and you do not want to use the
     /// default (BAT0). Check '/sys/class/power_supply/' to see which batteries you have.
-    #[arg(short = 'b', long)]
+    #[arg(short = 'b', long, global = true)]
     pub battery: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Inspect or update the config-file
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommands,
+    },
+    /// Send a request to the running daemon: 'status', 'reload', 'snooze <duration>',
+    /// 'test <level>', 'reset' or 'subscribe'
+    Control {
+        #[arg(required = true, num_args = 1..)]
+        request: Vec<String>,
+    },
+    /// Read or set the charge thresholds of the battery
+    ChargeLimit {
+        #[command(subcommand)]
+        action: ChargeLimitCommands,
+    },
+    /// Print the wear, cycle count and identification of the battery
+    Health {
+        /// Print the health as json
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Print the battery samples recorded by the daemon as a table, sparkline, csv or json
+    History {
+        /// Only show samples of the given time span, e.g. '30m', '24h' or '7d'
+        #[arg(long, default_value = "24h")]
+        since: String,
+        /// Print the levels as a sparkline instead of a table
+        #[arg(long, default_value_t = false, conflicts_with = "export")]
+        sparkline: bool,
+        /// Show the battery drain during each suspend instead of the samples
+        #[arg(long, default_value_t = false, conflicts_with = "sparkline")]
+        suspends: bool,
+        /// Print all fields of the samples as csv or json
+        #[arg(long, value_enum)]
+        export: Option<ExportFormat>,
+    },
+    /// Keep running and print a line whenever the battery-level or charging status changes, for
+    /// status bars such as waybar, i3blocks or polybar
+    Watch {
+        #[arg(long, value_enum, default_value_t = crate::watch::Format::Plain)]
+        format: crate::watch::Format,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ChargeLimitCommands {
+    /// Print the charge limit of the battery
+    Get,
+    /// Set the levels between which the battery is kept, e.g. 'set 60 80' or only the end 'set 80'.
+    /// Uses pkexec if the battery attributes are not writable.
+    Set {
+        #[arg(required = true, num_args = 1..=2, value_names = ["START", "END"])]
+        levels: Vec<u32>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// Print the config-file, with --effective the merged result of all config layers
+    Show {
+        /// Print the config merged from the system-wide config, the user config-file and all
+        /// 'config.d/*.json' drop-ins
+        #[arg(long, default_value_t = false)]
+        effective: bool,
+    },
+    /// Upgrade the config-file to the current version, the old file is kept as a backup
+    Migrate {
+        /// Overwrite the config-file without keeping a backup
+        #[arg(long, default_value_t = false)]
+        no_backup: bool,
+    },
 }
 
 /// used within build.rs
@@ -1,24 +1,154 @@
-use std::path::PathBuf;
+mod layers;
+mod migrate;
+
+use std::path::{Path, PathBuf};
 
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 
-use crate::notification::{BatteryFullNotification, Notification, Urgency};
+use crate::charge_limit::ChargeLimit;
+use crate::charger::ChargerConfig;
+use crate::drain::DrainConfig;
+use crate::history::HistoryConfig;
+use crate::hooks::HooksConfig;
+use crate::notification::{BatteryFullNotification, Notification, Urgency, DEFAULT_LIMIT_MESSAGE};
+use crate::temperature::TemperatureConfig;
+
+pub use layers::layer_files;
+pub use migrate::CURRENT_VERSION;
 
 pub const CRITICAL_WAIT_TIME_SECS: u32 = 10000;
 
 const CONFIG_NAME: &str = "config.json";
 
+const CONFIG_DIR_NAME: &str = "powernotd";
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
+    // schema version of the config-file, missing in files written before versioning was added
+    #[serde(default)]
+    pub version: u32,
     pub notifications: Vec<Notification>,
     pub full_notification: BatteryFullNotification,
+    // what to do about the current battery level when the daemon starts
+    #[serde(default)]
+    pub startup: StartupBehavior,
+    #[serde(default)]
+    pub suspend: SuspendConfig,
+    // how to handle several thresholds that are crossed between two checks
+    #[serde(default)]
+    pub crossing_policy: CrossingPolicy,
+    // settings for the commands run on notifications
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub sessions: SessionConfig,
+    #[serde(default)]
+    pub drain: DrainConfig,
+    #[serde(default)]
+    pub charger: ChargerConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub temperature: TemperatureConfig,
+    // samples of the battery kept for 'powernotd history'
+    #[serde(default)]
+    pub history: HistoryConfig,
+    // charge limit set on startup and after resume, left as it is if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charge_limit: Option<ChargeLimit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossingPolicy {
+    /// Only notify the most severe threshold, the others are skipped including their commands
+    #[default]
+    MostSevere,
+    /// Notify every crossed threshold and run its command from the highest to the lowest
+    AllInOrder,
+    /// Run the commands of all crossed thresholds but only notify the most severe one
+    AllCommandsMostSevereNotification,
+}
+
+/// Behavior around system suspend, the battery is checked right after every resume
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SuspendConfig {
+    // send 'Battery dropped from X% to Y% while suspended for Z' after resuming
+    #[serde(default)]
+    pub notify_on_resume: bool,
+    // notify when the battery drained faster than this many percent per hour while suspended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_percent_per_hour: Option<f64>,
+    #[serde(default = "default_resume_urgency")]
+    pub urgency: Urgency,
+}
+
+fn default_resume_urgency() -> Urgency {
+    Urgency::Low
+}
+
+impl Default for SuspendConfig {
+    fn default() -> Self {
+        SuspendConfig {
+            notify_on_resume: false,
+            max_percent_per_hour: None,
+            urgency: default_resume_urgency(),
+        }
+    }
+}
+
+/// Summaries sent when the charger is connected after discharging or disconnected after charging
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionConfig {
+    // send e.g. 'Used 43% over 3h12m (avg 9.8 W)' or 'Charged 20%→100% in 1h05m'
+    #[serde(default)]
+    pub notify_summary: bool,
+    #[serde(default = "default_session_urgency")]
+    pub urgency: Urgency,
+}
+
+fn default_session_urgency() -> Urgency {
+    Urgency::Low
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            notify_summary: false,
+            urgency: default_session_urgency(),
+        }
+    }
+}
+
+/// Notification about the wear of the battery, it is sent once
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct HealthConfig {
+    // notify when the full capacity falls below this percentage of the design capacity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_below: Option<u32>,
+    #[serde(default)]
+    pub urgency: Urgency,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupBehavior {
+    /// Notify the threshold the current level is already below
+    #[default]
+    NotifyCurrent,
+    /// Only notify thresholds below the level at startup
+    Silent,
+    /// Like silent but send a single notification with the level, status and time remaining
+    Summary,
 }
 
 #[derive(Debug)]
 pub enum Error {
     LoadConfigError,
     SaveDefaultConfigError,
+    UnsupportedConfigVersion(u32),
+    MigrateConfigError,
 }
 
 impl From<std::io::Error> for Error {
@@ -28,22 +158,27 @@ impl From<std::io::Error> for Error {
 }
 
 pub fn get_specific_config(file_path: PathBuf) -> Config {
-    match load_config_from_file(&file_path) {
-        Ok(config) => config,
-        Err(err) => {
-            eprintln!(
-                "Could not load file_path {}, error: {:?}",
-                file_path.to_string_lossy(),
-                err
-            );
-            get_default_config()
-        }
+    if !file_path.exists() {
+        eprintln!(
+            "Could not load file_path {}, file does not exist",
+            file_path.to_string_lossy()
+        );
     }
+    load_effective_config(Some(&file_path)).unwrap_or_else(|err| {
+        eprintln!(
+            "Could not load file_path {}, error: {:?}",
+            file_path.to_string_lossy(),
+            err
+        );
+        get_default_config()
+    })
 }
 
 pub fn get_or_create_config() -> Config {
-    if let Some(config_file) = get_default_config_path() {
-        if !config_file.exists() {
+    let config_file = get_default_config_path();
+    if let Some(config_file) = &config_file {
+        // a system-wide config replaces the defaults, so only create a user file without one
+        if !config_file.exists() && layers::system_config_files().is_empty() {
             if let Err(err) = save_default_config() {
                 eprintln!(
                     "could not save default configuration file to path: {}, err: {:?}",
@@ -51,31 +186,130 @@ pub fn get_or_create_config() -> Config {
                     err
                 );
             };
-            get_default_config()
-        } else {
-            load_config_from_file(&config_file).unwrap_or_else(|err| {
+        }
+    }
+    load_effective_config(config_file.as_deref()).unwrap_or_else(|err| {
+        eprintln!("Could not load config, error: {:?}", err);
+        get_default_config()
+    })
+}
+
+/// Load the config made up of the built-in defaults, the system-wide config-files, the given user
+/// config-file and all drop-ins. The first layer defining 'notifications' replaces the default
+/// thresholds, later layers are merged into them by level.
+pub fn load_effective_config(user_file: Option<&Path>) -> Result<Config, Error> {
+    merge_layers(layers::layer_files(user_file))
+}
+
+/// Merge the files on top of the defaults in the given order. A file that cannot be parsed or
+/// does not fit the schema once merged is skipped with a warning, the other layers still apply.
+fn merge_layers(files: Vec<PathBuf>) -> Result<Config, Error> {
+    let mut effective =
+        serde_json::to_value(get_default_config()).map_err(|_| Error::LoadConfigError)?;
+    let mut default_notifications = true;
+
+    for file in files {
+        let layer = match load_layer(&file) {
+            Ok((layer, previous_version, outdated)) => {
+                if outdated {
+                    eprintln!(
+                        "Config file {} uses version {}, it was upgraded in memory to version {}. Run 'powernotd config migrate -f {}' to update the file.",
+                        file.to_string_lossy(),
+                        previous_version,
+                        CURRENT_VERSION,
+                        file.to_string_lossy()
+                    );
+                }
+                layer
+            }
+            Err(err) => {
                 eprintln!(
-                    "Could not load config: {}, error: {:?}",
-                    config_file.to_string_lossy(),
+                    "Skipping config file {}, error: {:?}",
+                    file.to_string_lossy(),
                     err
                 );
-                get_default_config()
-            })
+                continue;
+            }
+        };
+        let replaces_defaults = default_notifications && layer.get("notifications").is_some();
+        let mut merged = effective.clone();
+        if replaces_defaults {
+            merged["notifications"] = serde_json::Value::Array(vec![]);
+        }
+        layers::merge(&mut merged, layer);
+        if let Err(error) = serde_json::from_value::<Config>(merged.clone()) {
+            eprintln!(
+                "Skipping config file {}, error: {}",
+                file.to_string_lossy(),
+                error
+            );
+            continue;
+        }
+        if replaces_defaults {
+            default_notifications = false;
+        }
+        effective = merged;
+    }
+
+    match serde_json::from_value::<Config>(effective) {
+        Ok(config) => Ok(config),
+        Err(error) => {
+            eprintln!("Error loading merged config, error: {}", error);
+            Err(Error::LoadConfigError)
         }
-    } else {
-        get_default_config()
     }
 }
 
-fn load_config_from_file(path: &PathBuf) -> Result<Config, Error> {
+/// Read a single, possibly partial, config layer and upgrade it to the current version. Returns
+/// the upgraded layer together with the version the file was written in and whether the file
+/// itself should be migrated, see migrate::is_outdated.
+fn load_layer(path: &Path) -> Result<(serde_json::Value, u32, bool), Error> {
     let text = std::fs::read_to_string(path)?;
-    match serde_json::from_str::<Config>(&text) {
-        Ok(config) => Ok(config),
+    let mut value = match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(value) => value,
         Err(error) => {
             eprintln!("Error loading config from file, error: {}", error);
-            Err(Error::LoadConfigError)
+            return Err(Error::LoadConfigError);
         }
+    };
+    let outdated = migrate::is_outdated(&value);
+    let previous_version = migrate::migrate(&mut value)?;
+    Ok((value, previous_version, outdated))
+}
+
+pub enum MigrationOutcome {
+    UpToDate,
+    Migrated {
+        from_version: u32,
+        backup: Option<PathBuf>,
+    },
+}
+
+/// Upgrade the config-file at path on disk to the current version. If backup is set the old file
+/// is kept next to it as '<name>.v<old-version>.bak'.
+pub fn migrate_config_file(path: &Path, backup: bool) -> Result<MigrationOutcome, Error> {
+    let (config, from_version, _) = load_layer(path)?;
+    if from_version == CURRENT_VERSION {
+        return Ok(MigrationOutcome::UpToDate);
     }
+
+    let backup = if backup {
+        let mut backup_name = path.file_name().unwrap_or_default().to_owned();
+        backup_name.push(format!(".v{}.bak", from_version));
+        let backup_path = path.with_file_name(backup_name);
+        std::fs::copy(path, &backup_path).map_err(|_| Error::MigrateConfigError)?;
+        Some(backup_path)
+    } else {
+        None
+    };
+
+    let string = serde_json::to_string_pretty(&config).map_err(|_| Error::MigrateConfigError)?;
+    std::fs::write(path, string).map_err(|_| Error::MigrateConfigError)?;
+
+    Ok(MigrationOutcome::Migrated {
+        from_version,
+        backup,
+    })
 }
 
 pub fn get_default_config_path() -> Option<PathBuf> {
@@ -123,75 +357,68 @@ fn save_default_config() -> Result<(), Error> {
 }
 
 fn get_config_dir() -> Option<PathBuf> {
-    ProjectDirs::from("me", "laeri", "powernotd").map(|dir| dir.config_dir().to_owned())
+    ProjectDirs::from("me", "laeri", CONFIG_DIR_NAME).map(|dir| dir.config_dir().to_owned())
 }
 
-fn get_default_config() -> Config {
+pub(crate) fn get_default_config() -> Config {
     let default_title = "Battery Status";
     let default_message = "{}%";
     let notifications = vec![
         Notification {
             level: 30,
             urgency: Urgency::Low,
-            notified: false,
             time_secs: None,
-            command: None,
             title: Some(default_title.to_string()),
             message: Some(default_message.to_string()),
+            ..Default::default()
         },
         Notification {
             level: 20,
             urgency: Urgency::Normal,
-            notified: false,
             time_secs: None,
-            command: None,
             title: Some(default_title.to_string()),
             message: Some(default_message.to_string()),
+            ..Default::default()
         },
         Notification {
             level: 15,
             urgency: Urgency::Critical,
-            notified: false,
             time_secs: Some(CRITICAL_WAIT_TIME_SECS),
-            command: None,
             title: Some(default_title.to_string()),
             message: Some(default_message.to_string()),
+            ..Default::default()
         },
         Notification {
             level: 10,
             urgency: Urgency::Critical,
-            notified: false,
             time_secs: Some(CRITICAL_WAIT_TIME_SECS),
-            command: None,
             title: Some(default_title.to_string()),
             message: Some(default_message.to_string()),
+            ..Default::default()
         },
         Notification {
             level: 5,
             urgency: Urgency::Critical,
-            notified: false,
             time_secs: Some(CRITICAL_WAIT_TIME_SECS),
-            command: None,
             title: Some("Critical Battery Status".to_string()),
             message: Some(default_message.to_string()),
+            ..Default::default()
         },
         Notification {
             level: 2,
             urgency: Urgency::Critical,
-            notified: false,
             time_secs: Some(CRITICAL_WAIT_TIME_SECS),
-            command: None,
             title: Some("Critical Battery Status".to_string()),
             message: Some(default_message.to_string()),
+            ..Default::default()
         },
         Notification {
             level: 1,
             urgency: Urgency::Critical,
-            notified: false,
             time_secs: Some(CRITICAL_WAIT_TIME_SECS),
-            command: None,
             title: Some("Critical Battery Status".to_string()),
             message: Some(default_message.to_string()),
+            ..Default::default()
         },
     ];
 
@@ -200,13 +427,55 @@ fn get_default_config() -> Config {
         notified: false,
         time_secs: None,
         enabled: true,
-        command: None,
+        commands: vec![],
         title: Some("Battery Status".to_string()),
         message: Some("Fully Charged 100%".to_string()),
+        limit_message: Some(DEFAULT_LIMIT_MESSAGE.to_string()),
     };
 
     Config {
+        version: CURRENT_VERSION,
         notifications,
         full_notification,
+        startup: StartupBehavior::NotifyCurrent,
+        suspend: SuspendConfig::default(),
+        crossing_policy: CrossingPolicy::MostSevere,
+        hooks: HooksConfig::default(),
+        sessions: SessionConfig::default(),
+        drain: DrainConfig::default(),
+        charger: ChargerConfig::default(),
+        health: HealthConfig::default(),
+        temperature: TemperatureConfig::default(),
+        history: HistoryConfig::default(),
+        charge_limit: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn layer_with_schema_error_is_skipped() {
+        let dir = TestDir::new("layers");
+        let valid = dir.join("10-valid.json");
+        let invalid = dir.join("20-invalid.json");
+        std::fs::write(&valid, r#"{"startup": "silent"}"#).unwrap();
+        std::fs::write(
+            &invalid,
+            r#"{"notifications": [{"level": 5, "urgency": "Loud"}]}"#,
+        )
+        .unwrap();
+        // a partial drop-in without a version does not need to be migrated
+        let (_, _, outdated) = load_layer(&valid).unwrap();
+        assert!(!outdated);
+
+        let config = merge_layers(vec![valid, invalid]).unwrap();
+        assert_eq!(config.startup, StartupBehavior::Silent);
+        assert_eq!(
+            config.notifications.len(),
+            get_default_config().notifications.len()
+        );
     }
 }
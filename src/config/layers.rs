@@ -0,0 +1,185 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use super::{CONFIG_DIR_NAME, CONFIG_NAME};
+use crate::notification::level_of;
+
+/// Directory next to a config-file whose '*.json' entries are merged on top of it
+pub const DROP_IN_DIR_NAME: &str = "config.d";
+
+const DEFAULT_SYSTEM_CONFIG_DIRS: &str = "/etc/xdg";
+
+/// System-wide config directories in order of increasing precedence. Follows $XDG_CONFIG_DIRS
+/// where the first entry is the most important one.
+pub fn system_config_dirs() -> Vec<PathBuf> {
+    let dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or(DEFAULT_SYSTEM_CONFIG_DIRS.to_string());
+
+    dirs.split(':')
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .rev()
+        .map(|dir| dir.join(CONFIG_DIR_NAME))
+        .collect()
+}
+
+/// System config-files that exist, in order of increasing precedence
+pub fn system_config_files() -> Vec<PathBuf> {
+    system_config_dirs()
+        .into_iter()
+        .map(|dir| dir.join(CONFIG_NAME))
+        .filter(|file| file.exists())
+        .collect()
+}
+
+/// All files making up the effective config in order of increasing precedence: system files,
+/// system drop-ins, the user file and the user drop-ins
+pub fn layer_files(user_file: Option<&Path>) -> Vec<PathBuf> {
+    let mut layers = vec![];
+    for dir in system_config_dirs() {
+        let file = dir.join(CONFIG_NAME);
+        if file.exists() {
+            layers.push(file);
+        }
+        layers.extend(drop_in_files(&dir.join(DROP_IN_DIR_NAME)));
+    }
+    if let Some(user_file) = user_file {
+        if user_file.exists() {
+            layers.push(user_file.to_owned());
+        }
+        if let Some(dir) = user_file.parent() {
+            layers.extend(drop_in_files(&dir.join(DROP_IN_DIR_NAME)));
+        }
+    }
+    layers
+}
+
+/// '*.json' files within dir sorted by name, so '10-foo.json' is applied before '20-bar.json'
+fn drop_in_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut files = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// Merge overlay into base by key. Objects are merged recursively, entries of the
+/// 'notifications' array are matched by their 'level' and everything else is replaced.
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(Value::Array(entries)) if key == "notifications" => {
+                        merge_notifications(entries, value)
+                    }
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn merge_notifications(entries: &mut Vec<Value>, overlay: Value) {
+    let Value::Array(overlay) = overlay else {
+        return;
+    };
+    for notification in overlay {
+        // 15 and "15" or "Low" and "low" are the same threshold
+        let level = level_of(&notification);
+        let existing = entries
+            .iter_mut()
+            .find(|entry| level.is_some() && level_of(entry) == level);
+        match existing {
+            Some(existing) => merge(existing, notification),
+            None => entries.push(notification),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_are_merged_by_level() {
+        let mut base = serde_json::json!({
+            "notifications": [
+                { "level": 20, "urgency": "Normal", "title": "Battery" },
+                { "level": 10, "urgency": "Critical" }
+            ],
+            "full_notification": { "enabled": true, "urgency": "Low" }
+        });
+        let overlay = serde_json::json!({
+            "notifications": [
+                { "level": 20, "title": "Low Battery" },
+                { "level": 5, "urgency": "Critical" }
+            ],
+            "full_notification": { "enabled": false }
+        });
+
+        merge(&mut base, overlay);
+
+        assert_eq!(
+            base,
+            serde_json::json!({
+                "notifications": [
+                    { "level": 20, "urgency": "Normal", "title": "Low Battery" },
+                    { "level": 10, "urgency": "Critical" },
+                    { "level": 5, "urgency": "Critical" }
+                ],
+                "full_notification": { "enabled": false, "urgency": "Low" }
+            })
+        );
+    }
+
+    #[test]
+    fn levels_are_compared_after_normalizing() {
+        let mut base = serde_json::json!({
+            "notifications": [
+                { "level": 15, "urgency": "Normal" },
+                { "level": "Critical", "urgency": "Critical" }
+            ]
+        });
+        let overlay = serde_json::json!({
+            "notifications": [
+                { "level": "15", "title": "Low Battery" },
+                { "level": "critical", "title": "Critical Battery" }
+            ]
+        });
+
+        merge(&mut base, overlay);
+
+        assert_eq!(
+            base,
+            serde_json::json!({
+                "notifications": [
+                    { "level": "15", "urgency": "Normal", "title": "Low Battery" },
+                    { "level": "critical", "urgency": "Critical", "title": "Critical Battery" }
+                ]
+            })
+        );
+        let levels = base["notifications"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|notification| {
+                serde_json::from_value::<crate::notification::Notification>(notification.clone())
+                    .unwrap()
+                    .level
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(levels, vec![15, 5]);
+    }
+}
@@ -0,0 +1,150 @@
+use serde_json::{Map, Value};
+
+use super::Error;
+
+/// Version written to new config-files, bump it together with a new entry in MIGRATIONS
+pub const CURRENT_VERSION: u32 = 2;
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// Migrations indexed by the version they upgrade from, MIGRATIONS[n] turns version n into n + 1
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1, migrate_v1_to_v2];
+
+/// Return the version of a raw config, files written before versioning was introduced have none
+pub fn version_of(config: &Value) -> u32 {
+    config.get("version").and_then(Value::as_u64).unwrap_or(0) as u32
+}
+
+/// True if the raw config declares an older version or upgrading it changes more than the version.
+/// Partial layers without a version that only use current keys are up to date.
+pub fn is_outdated(config: &Value) -> bool {
+    if config.get("version").is_some() {
+        return version_of(config) < CURRENT_VERSION;
+    }
+    let mut migrated = config.clone();
+    if migrate(&mut migrated).is_err() {
+        return false;
+    }
+    if let Some(object) = migrated.as_object_mut() {
+        object.remove("version");
+    }
+    migrated != *config
+}
+
+/// Upgrade a raw config in place to CURRENT_VERSION. Returns the version the config had before.
+pub fn migrate(config: &mut Value) -> Result<u32, Error> {
+    let version = version_of(config);
+    if version > CURRENT_VERSION {
+        return Err(Error::UnsupportedConfigVersion(version));
+    }
+
+    let object = config.as_object_mut().ok_or(Error::LoadConfigError)?;
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(object);
+    }
+    // keep the version as first key when it is added to an unversioned file
+    let mut migrated = Map::new();
+    migrated.insert("version".to_string(), Value::from(CURRENT_VERSION));
+    migrated.extend(
+        std::mem::take(object)
+            .into_iter()
+            .filter(|(key, _)| key != "version"),
+    );
+    *object = migrated;
+
+    Ok(version)
+}
+
+/// Version 0 is the unversioned layout, apart from the new version key nothing changed
+fn migrate_v0_to_v1(_config: &mut Map<String, Value>) {}
+
+/// Version 2 replaced the single 'command' of notifications with a list of 'commands'
+fn migrate_v1_to_v2(config: &mut Map<String, Value>) {
+    if let Some(Value::Array(notifications)) = config.get_mut("notifications") {
+        for notification in notifications.iter_mut().filter_map(Value::as_object_mut) {
+            command_to_commands(notification);
+        }
+    }
+    if let Some(Value::Object(full_notification)) = config.get_mut("full_notification") {
+        command_to_commands(full_notification);
+    }
+}
+
+fn command_to_commands(notification: &mut Map<String, Value>) {
+    // rebuild the map to keep the position of the key
+    *notification = std::mem::take(notification)
+        .into_iter()
+        .map(|(key, value)| match key.as_str() {
+            // 'command': null stood for no command
+            "command" if value.is_null() => ("commands".to_string(), Value::Array(vec![])),
+            "command" => ("commands".to_string(), Value::Array(vec![value])),
+            _ => (key, value),
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unversioned_config_is_upgraded() {
+        let mut config = serde_json::json!({ "notifications": [] });
+        assert_eq!(migrate(&mut config).unwrap(), 0);
+        assert_eq!(version_of(&config), CURRENT_VERSION);
+    }
+
+    #[test]
+    fn single_command_becomes_list() {
+        let mut config = serde_json::json!({
+            "version": 1,
+            "notifications": [{ "level": 5, "command": "systemctl hibernate", "title": "Low" }],
+            "full_notification": { "command": { "run": "charged.sh" } }
+        });
+        assert_eq!(migrate(&mut config).unwrap(), 1);
+        assert_eq!(
+            config,
+            serde_json::json!({
+                "version": CURRENT_VERSION,
+                "notifications": [{ "level": 5, "commands": ["systemctl hibernate"], "title": "Low" }],
+                "full_notification": { "commands": [{ "run": "charged.sh" }] }
+            })
+        );
+    }
+
+    #[test]
+    fn null_command_becomes_empty_list() {
+        let mut config = serde_json::json!({
+            "version": 1,
+            "notifications": [{ "level": 5, "urgency": "Critical", "command": null }]
+        });
+        migrate(&mut config).unwrap();
+        assert_eq!(
+            config["notifications"][0]["commands"],
+            serde_json::json!([])
+        );
+        let notification: crate::notification::Notification =
+            serde_json::from_value(config["notifications"][0].clone()).unwrap();
+        assert!(notification.commands.is_empty());
+    }
+
+    #[test]
+    fn partial_layer_without_version_is_up_to_date() {
+        assert!(!is_outdated(&serde_json::json!({ "startup": "silent" })));
+        assert!(is_outdated(
+            &serde_json::json!({ "version": 1, "startup": "silent" })
+        ));
+        assert!(is_outdated(&serde_json::json!({
+            "notifications": [{ "level": 5, "command": "systemctl hibernate" }]
+        })));
+    }
+
+    #[test]
+    fn newer_config_is_rejected() {
+        let mut config = serde_json::json!({ "version": CURRENT_VERSION + 1 });
+        assert!(matches!(
+            migrate(&mut config),
+            Err(Error::UnsupportedConfigVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,210 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::daemon::{DaemonStatus, Event};
+use crate::duration::parse_duration;
+
+const SOCKET_NAME: &str = "powernotd.sock";
+
+/// Requests understood by the daemon, sent as a single line such as 'snooze 30m'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Reply with the current status
+    Status,
+    /// Load the config-file again
+    Reload,
+    /// Do not show threshold notifications for the given duration, zero ends a snooze
+    Snooze(Duration),
+    /// Show the notification of the threshold for the given level without running its commands
+    Test(u32),
+    /// Forget which thresholds were notified and end a snooze
+    Reset,
+    /// Keep the connection open and reply with the status whenever it changes
+    Subscribe,
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Request, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let argument = words.next();
+        if words.next().is_some() {
+            return Err(format!("too many arguments for '{}'", command));
+        }
+
+        match (command, argument) {
+            ("status", None) => Ok(Request::Status),
+            ("reload", None) => Ok(Request::Reload),
+            ("snooze", Some(duration)) => parse_duration(duration)
+                .map(Request::Snooze)
+                .ok_or(format!("invalid duration '{}'", duration)),
+            ("test", Some(level)) => level
+                .trim_end_matches('%')
+                .parse()
+                .map(Request::Test)
+                .map_err(|_| format!("invalid level '{}'", level)),
+            ("reset", None) => Ok(Request::Reset),
+            ("subscribe", None) => Ok(Request::Subscribe),
+            ("snooze" | "test", None) => Err(format!("missing argument for '{}'", command)),
+            _ => Err(format!("unknown request '{}'", line.trim())),
+        }
+    }
+}
+
+/// Reply to a request, written as a single line of json
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<DaemonStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Response {
+            ok: true,
+            status: None,
+            error: None,
+        }
+    }
+
+    pub fn status(status: DaemonStatus) -> Self {
+        Response {
+            ok: true,
+            status: Some(status),
+            error: None,
+        }
+    }
+
+    pub fn error(error: String) -> Self {
+        Response {
+            ok: false,
+            status: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Socket of the running daemon, $XDG_RUNTIME_DIR/powernotd.sock
+pub fn get_socket_path() -> Option<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(runtime_dir).join(SOCKET_NAME))
+}
+
+/// Listen on the control socket on a background thread and pass requests to the daemon. Fails if
+/// another daemon is already listening.
+pub fn listen(path: &Path, sender: Sender<Event>) -> std::io::Result<()> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("{} is used by another daemon", path.to_string_lossy()),
+            ));
+        }
+        // left behind by a daemon that did not shut down cleanly
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    thread::spawn(move || handle_connection(stream, sender));
+                }
+                Err(err) => eprintln!("Control socket error: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle_connection(stream: UnixStream, sender: Sender<Event>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = match Request::parse(&line) {
+            Ok(request) => request,
+            Err(err) => {
+                if write_response(&mut writer, &Response::error(err)).is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+        let subscribe = request == Request::Subscribe;
+        let (reply, responses) = async_channel::unbounded();
+        if sender.send(Event::Control(request, reply)).is_err() {
+            return;
+        }
+
+        // a subscription keeps receiving responses until the client disconnects
+        while let Ok(response) = responses.recv_blocking() {
+            if write_response(&mut writer, &response).is_err() {
+                return;
+            }
+            if !subscribe {
+                break;
+            }
+        }
+    }
+}
+
+fn write_response(writer: &mut UnixStream, response: &Response) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+/// Connect to the running daemon, fails if no daemon is listening
+pub fn connect() -> std::io::Result<UnixStream> {
+    let path = get_socket_path().ok_or(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "XDG_RUNTIME_DIR is not set",
+    ))?;
+    UnixStream::connect(path)
+}
+
+/// Send a request to the running daemon and return its response
+pub fn request(line: &str) -> std::io::Result<Response> {
+    let mut stream = connect()?;
+    stream.write_all(format!("{}\n", line).as_bytes())?;
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    serde_json::from_str(&response).map_err(std::io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(Request::parse("status"), Ok(Request::Status));
+        assert_eq!(
+            Request::parse("snooze 30m"),
+            Ok(Request::Snooze(Duration::from_secs(1800)))
+        );
+        assert_eq!(Request::parse("test 15%"), Ok(Request::Test(15)));
+        assert!(Request::parse("snooze").is_err());
+        assert!(Request::parse("reset now").is_err());
+        assert!(Request::parse("shutdown").is_err());
+    }
+}
@@ -0,0 +1,1240 @@
+use std::collections::HashMap;
+use std::os::fd::OwnedFd;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::battery::{
+    get_battery_dir, get_charge_limit, get_energy_wh, get_health_percent, get_temperature_c,
+    get_time_remaining, get_usb_supply_watts, is_ac_online, read_level, read_snapshot, read_status,
+};
+use crate::charger::ChargerMonitor;
+use crate::config::{self, Config, CrossingPolicy, StartupBehavior};
+use crate::control::{self, Request, Response};
+use crate::drain::DrainMonitor;
+use crate::duration::format_duration;
+use crate::history::SuspendEntry;
+use crate::hooks::{HookEvent, HooksConfig};
+use crate::notification::{
+    CommandSpec, Notification, PowerAction, Urgency, DEFAULT_ACTION_COUNTDOWN_SECS,
+};
+use crate::session::Session;
+use crate::state::{self, State};
+use crate::*;
+use crate::{charge_limit, dbus, history, hooks, logind, power_action, temperature, template};
+use serde::{Deserialize, Serialize};
+
+/// Interval in which the battery level is polled
+pub const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Consecutive failed battery reads after which the user is notified once
+const SENSOR_FAILURES_BEFORE_NOTIFICATION: u32 = 5;
+const INITIAL_READ_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Interval in which AC is checked so plugging in is published before the next poll
+const AC_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Events handled by the daemon in between polling the battery
+#[derive(Debug)]
+pub enum Event {
+    /// The system is about to suspend, it waits until the delay lock is dropped
+    Suspending(Option<OwnedFd>),
+    /// The system resumed from suspend
+    Resumed,
+    /// An AC adapter or USB power supply went online or offline
+    AcChanged,
+    /// A request from the control socket or D-Bus, responses are sent back through the sender
+    /// which can be awaited by async callers as well
+    Control(Request, async_channel::Sender<Response>),
+}
+
+/// Snapshot of the daemon reported to control socket clients
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DaemonStatus {
+    pub level: u32,
+    pub status: ChargingStatus,
+    pub battery: String,
+    // lowest threshold at or above the current level
+    pub active_threshold: Option<u32>,
+    // unix timestamp until which notifications are snoozed
+    pub snoozed_until: Option<u64>,
+    pub time_remaining_secs: Option<u64>,
+}
+
+/// Shows a desktop notification, send_message_in_background outside of tests so the retries do
+/// not block the event loop
+type Notify = fn(&str, &str, &Urgency, Option<u32>) -> Result<(), Error>;
+
+/// Runs the command lists one after another in the background, hooks::spawn_in_order outside of
+/// tests
+type RunCommands = fn(Vec<(Vec<CommandSpec>, HookEvent)>, &HooksConfig);
+
+/// Starts the countdown of a power action, power_action::start_countdown outside of tests
+type StartCountdown = fn(PowerAction, Duration, Option<&Battery>) -> thread::JoinHandle<()>;
+
+/// Battery level and wall-clock time recorded right before the system suspended
+struct SuspendRecord {
+    level: u32,
+    at: u64,
+    energy_wh: Option<f64>,
+}
+
+/// Suspends shorter than this are not checked against the maximum drain rate, losing a single
+/// percent in a few minutes would already exceed it
+const MIN_CHECKED_SUSPEND: Duration = Duration::from_secs(30 * 60);
+
+/// The notification daemon which polls the battery and sends notifications for thresholds
+pub struct Daemon {
+    config: Config,
+    // config-file passed on the command line, used when reloading
+    config_file: Option<PathBuf>,
+    battery: Option<String>,
+    // sysfs directory the battery attributes are read from
+    battery_dir: PathBuf,
+    // file the state is saved to, None if there is no state directory
+    state_path: Option<PathBuf>,
+    notify: Notify,
+    run_commands: RunCommands,
+    start_countdown: StartCountdown,
+    // notifications by threshold level
+    notified: HashMap<u32, Notification>,
+    // unix timestamps of when the thresholds currently marked as notified were notified
+    notified_at: HashMap<u32, u64>,
+    // None until the first reading or a restored state
+    last_battery_level: Option<u32>,
+    read_failures: u32,
+    // countdown of a power action such as hibernate that is currently running
+    power_action: Option<thread::JoinHandle<()>>,
+    suspended: Option<SuspendRecord>,
+    // unix timestamp until which threshold notifications are not shown
+    snoozed_until: Option<u64>,
+    // control socket clients waiting for status changes
+    subscribers: Vec<async_channel::Sender<Response>>,
+    last_published: Option<DaemonStatus>,
+    // current discharge or charge session, starts with the first successful read
+    session: Option<Session>,
+    drain: DrainMonitor,
+    charger: ChargerMonitor,
+    // the health notification was already sent, it is only sent once
+    health_notified: bool,
+    // the charge limit could not be written, this is only logged once
+    charge_limit_skipped: bool,
+    // object exported on the session bus
+    dbus: Option<dbus::Service>,
+    sender: Sender<Event>,
+    events: Receiver<Event>,
+}
+
+impl Daemon {
+    pub fn new(
+        mut config: Config,
+        battery: Option<&Battery>,
+        config_file: Option<PathBuf>,
+    ) -> Self {
+        let notified = notifications_by_level(&mut config);
+
+        let (sender, events) = mpsc::channel();
+        Daemon {
+            config,
+            config_file,
+            battery: battery.map(|battery| battery.to_owned()),
+            battery_dir: get_battery_dir(battery),
+            state_path: state::get_state_path(battery),
+            notify: send_message_in_background,
+            run_commands: hooks::spawn_in_order,
+            start_countdown: power_action::start_countdown,
+            notified,
+            notified_at: HashMap::new(),
+            last_battery_level: None,
+            read_failures: 0,
+            power_action: None,
+            suspended: None,
+            snoozed_until: None,
+            subscribers: vec![],
+            last_published: None,
+            session: None,
+            drain: DrainMonitor::default(),
+            charger: ChargerMonitor::default(),
+            health_notified: false,
+            charge_limit_skipped: false,
+            dbus: None,
+            sender,
+            events,
+        }
+    }
+
+    /// Continue with the state saved by a previous run if it is recent enough. Returns true if a
+    /// state was restored.
+    pub fn restore_state(&mut self) -> bool {
+        let Some(state) = self.state_path.as_deref().and_then(state::load) else {
+            return false;
+        };
+        self.health_notified = state.health_notified;
+        if !state.is_recent() {
+            return false;
+        }
+
+        self.last_battery_level = Some(state.last_level);
+        for (level, notified_at) in state.notified {
+            if let Some(notification) = self.notified.get_mut(&level) {
+                notification.notified = true;
+                self.notified_at.insert(level, notified_at);
+            }
+        }
+        self.config.full_notification.notified = state.full_notified;
+        true
+    }
+
+    /// Apply the configured startup behavior, called instead of restore_state when there is no
+    /// recent saved state
+    pub fn startup(&mut self) {
+        if self.config.startup == StartupBehavior::NotifyCurrent {
+            return;
+        }
+        let level = match read_level(&self.battery_dir) {
+            Ok(level) => level,
+            Err(err) => {
+                eprintln!("Could not read battery level: {}", err);
+                return;
+            }
+        };
+
+        // treat the current threshold as notified so only thresholds below the current level fire
+        self.last_battery_level = Some(level);
+        if let Some(threshold_val) = find_lowest_threshold(level, &self.notified) {
+            if let Some(notification) = self.notified.get_mut(&threshold_val) {
+                notification.notified = true;
+                self.notified_at.insert(threshold_val, state::now_secs());
+            }
+        }
+
+        if self.config.startup == StartupBehavior::Summary {
+            if let Err(err) = self.send_summary(level) {
+                eprintln!("{}", err);
+            }
+        }
+    }
+
+    fn send_summary(&self, level: u32) -> Result<(), Error> {
+        let status = read_status(&self.battery_dir)?;
+        let mut message = format!("{}%, {}", level, status);
+        let charging = status == ChargingStatus::Charging;
+        if let Some(remaining) = get_time_remaining(&self.battery_dir, charging) {
+            let until = if charging { "until full" } else { "remaining" };
+            message.push_str(&format!(", {} {}", format_duration(remaining), until));
+        }
+        self.send_message("Battery Status", &message, &Urgency::Low)
+    }
+
+    fn send_message(&self, title: &str, message: &str, urgency: &Urgency) -> Result<(), Error> {
+        (self.notify)(title, message, urgency, None)
+    }
+
+    /// Handle a threshold that was crossed together with a more severe one according to the
+    /// crossing policy. Returns its commands, they are run together with the commands of the other
+    /// crossed thresholds.
+    fn notify_skipped_threshold(
+        &self,
+        level: u32,
+        threshold_val: u32,
+    ) -> Option<(Vec<CommandSpec>, HookEvent)> {
+        let notification = self.notified.get(&threshold_val)?;
+        let event = self.hook_event("threshold", level, Some(threshold_val));
+        match self.config.crossing_policy {
+            CrossingPolicy::MostSevere => return None,
+            CrossingPolicy::AllInOrder if !self.is_snoozed() => {
+                if let Err(err) = self.send_notification(notification, &event) {
+                    eprintln!("{}", err);
+                }
+            }
+            CrossingPolicy::AllInOrder | CrossingPolicy::AllCommandsMostSevereNotification => {}
+        }
+        Some((notification.commands.clone(), event))
+    }
+
+    /// Send the title and message of the notification templated with the values of the event
+    fn send_notification(
+        &self,
+        notification: &Notification,
+        event: &HookEvent,
+    ) -> Result<(), Error> {
+        let snapshot = read_snapshot(
+            &self.battery_dir,
+            event.level,
+            event.status,
+            event.threshold,
+        );
+        let (title, message) = render_notification(notification, &snapshot);
+        (self.notify)(
+            &title,
+            &message,
+            &notification.urgency,
+            notification.time_secs,
+        )
+    }
+
+    /// Describe an event for the commands run on it
+    fn hook_event(&self, event: &str, level: u32, threshold: Option<u32>) -> HookEvent {
+        let battery = self.battery.as_deref();
+        HookEvent {
+            event: event.to_string(),
+            level,
+            status: read_status(&self.battery_dir).unwrap_or(ChargingStatus::Unknown),
+            battery: battery.unwrap_or(DEFAULT_BATTERY).to_string(),
+            threshold,
+        }
+    }
+
+    /// Start the power action of the most severe crossed threshold that has one. With the
+    /// most_severe policy only the action of the most severe threshold itself is considered.
+    fn start_threshold_action(&mut self, crossed: &[u32]) {
+        let candidates = match self.config.crossing_policy {
+            CrossingPolicy::MostSevere => &crossed[crossed.len() - 1..],
+            _ => crossed,
+        };
+        let action = candidates
+            .iter()
+            .rev()
+            .filter_map(|threshold_val| self.notified.get(threshold_val))
+            .find_map(|notification| {
+                let countdown = notification
+                    .action_countdown_secs
+                    .unwrap_or(DEFAULT_ACTION_COUNTDOWN_SECS);
+                notification
+                    .action
+                    .map(|action| (action, Duration::from_secs(countdown.into())))
+            });
+        if let Some((action, countdown)) = action {
+            self.start_power_action(action, countdown);
+        }
+    }
+
+    fn start_power_action(&mut self, action: PowerAction, countdown: Duration) {
+        let running = self
+            .power_action
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished());
+        if running {
+            eprintln!(
+                "Not starting {}, another power action is pending",
+                action.as_str()
+            );
+            return;
+        }
+        self.power_action = Some((self.start_countdown)(
+            action,
+            countdown,
+            self.battery.as_deref(),
+        ));
+    }
+
+    fn save_state(&self) {
+        let Some(path) = &self.state_path else {
+            return;
+        };
+        let state = State {
+            saved_at: state::now_secs(),
+            last_level: self.last_battery_level.unwrap_or_default(),
+            notified: self
+                .notified
+                .values()
+                .filter(|notification| notification.notified)
+                .map(|notification| {
+                    let notified_at = self.notified_at.get(&notification.level);
+                    (notification.level, notified_at.copied().unwrap_or_default())
+                })
+                .collect(),
+            full_notified: self.config.full_notification.notified,
+            health_notified: self.health_notified,
+        };
+        if let Err(err) = state::save(path, &state) {
+            eprintln!("Could not save state, error: {}", err);
+        }
+    }
+
+    /// Sender for passing events from other threads to the daemon
+    pub fn event_sender(&self) -> Sender<Event> {
+        self.sender.clone()
+    }
+
+    pub fn run(mut self) -> ! {
+        self.watch_sleep();
+        self.watch_ac();
+        self.reapply_charge_limit();
+        if let Some(path) = control::get_socket_path() {
+            if let Err(err) = control::listen(&path, self.event_sender()) {
+                eprintln!("Not listening on control socket: {}", err);
+            }
+        }
+        match dbus::Service::start(self.event_sender()) {
+            Ok(service) => self.dbus = Some(service),
+            Err(err) => eprintln!("Not exporting the D-Bus interface: {}", err),
+        }
+
+        let mut next_tick = Instant::now();
+        loop {
+            let timeout = next_tick.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(timeout) {
+                Ok(event) => {
+                    if self.handle_event(event) {
+                        next_tick = Instant::now();
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    next_tick = Instant::now() + self.tick();
+                }
+                // the daemon holds a sender itself so the channel never disconnects
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+        }
+    }
+
+    /// Set the configured charge limit if the daemon may write it. The daemon never asks for
+    /// authentication, 'powernotd charge-limit set' does.
+    fn reapply_charge_limit(&mut self) {
+        let Some(limit) = self.config.charge_limit else {
+            return;
+        };
+        if let Err(err) = charge_limit::check_writable(&self.battery_dir) {
+            if !self.charge_limit_skipped {
+                self.charge_limit_skipped = true;
+                eprintln!("Not setting charge limit {}, {}", limit, err);
+            }
+            return;
+        }
+        match charge_limit::reapply(&self.battery_dir, &limit) {
+            Ok(true) => eprintln!("Charge limit set to {}", limit),
+            Ok(false) => {}
+            Err(err) => eprintln!("Could not set charge limit: {}", err),
+        }
+    }
+
+    /// Forward logind's suspend and resume signals as events
+    fn watch_sleep(&self) {
+        let sender = self.event_sender();
+        thread::spawn(move || {
+            let result = logind::watch_sleep(|start, lock| {
+                let event = if start {
+                    Event::Suspending(lock)
+                } else {
+                    Event::Resumed
+                };
+                let _ = sender.send(event);
+            });
+            if let Err(err) = result {
+                eprintln!("Not watching for suspend and resume: {}", err);
+            }
+        });
+    }
+
+    /// Send an event whenever AC goes online or offline
+    fn watch_ac(&self) {
+        let sender = self.event_sender();
+        thread::spawn(move || {
+            let mut online = is_ac_online();
+            loop {
+                thread::sleep(AC_POLL_INTERVAL);
+                let now_online = is_ac_online();
+                if now_online == online {
+                    continue;
+                }
+                online = now_online;
+                if sender.send(Event::AcChanged).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Handle an event, returns true if the battery should be checked right away
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::Suspending(lock) => {
+                self.suspended = read_level(&self.battery_dir)
+                    .ok()
+                    .map(|level| SuspendRecord {
+                        level,
+                        at: state::now_secs(),
+                        energy_wh: get_energy_wh(&self.battery_dir),
+                    });
+                // the system suspends once the battery was recorded
+                drop(lock);
+                false
+            }
+            Event::Resumed => {
+                if let Some(suspended) = self.suspended.take() {
+                    self.report_resume(&suspended);
+                }
+                self.reapply_charge_limit();
+                true
+            }
+            Event::AcChanged => {
+                // the charging status changed, subscribers should not wait for the next tick
+                self.publish_status();
+                false
+            }
+            Event::Control(request, reply) => {
+                self.handle_request(request, reply);
+                false
+            }
+        }
+    }
+
+    fn handle_request(&mut self, request: Request, reply: async_channel::Sender<Response>) {
+        let response = match request {
+            Request::Status => match self.status() {
+                Ok(status) => Response::status(status),
+                Err(err) => Response::error(err.to_string()),
+            },
+            Request::Reload => {
+                self.reload();
+                Response::ok()
+            }
+            Request::Snooze(duration) => {
+                self.snoozed_until =
+                    (!duration.is_zero()).then(|| state::now_secs() + duration.as_secs());
+                Response::ok()
+            }
+            Request::Test(level) => match self.send_test_notification(level) {
+                Ok(()) => Response::ok(),
+                Err(err) => Response::error(err),
+            },
+            Request::Reset => {
+                for notification in self.notified.values_mut() {
+                    notification.notified = false;
+                }
+                self.notified_at.clear();
+                self.config.full_notification.notified = false;
+                self.snoozed_until = None;
+                Response::ok()
+            }
+            Request::Subscribe => {
+                if let Ok(status) = self.status() {
+                    let _ = reply.try_send(Response::status(status));
+                }
+                self.subscribers.push(reply);
+                return;
+            }
+        };
+        let _ = reply.try_send(response);
+        self.publish_status();
+    }
+
+    /// Load the config-file again, thresholds that still exist keep their notified state
+    fn reload(&mut self) {
+        let mut config = match &self.config_file {
+            Some(path) => config::get_specific_config(path.clone()),
+            None => config::get_or_create_config(),
+        };
+        let mut notified = notifications_by_level(&mut config);
+        for (level, notification) in notified.iter_mut() {
+            notification.notified = self
+                .notified
+                .get(level)
+                .is_some_and(|previous| previous.notified);
+        }
+        config.full_notification.notified = self.config.full_notification.notified;
+        for notification in &mut config.temperature.notifications {
+            notification.notified = self
+                .config
+                .temperature
+                .notifications
+                .iter()
+                .any(|previous| previous.level == notification.level && previous.notified);
+        }
+        self.notified_at
+            .retain(|level, _| notified.contains_key(level));
+        self.notified = notified;
+        self.config = config;
+        self.reapply_charge_limit();
+    }
+
+    fn send_test_notification(&self, level: u32) -> Result<(), String> {
+        let notification = find_lowest_threshold(level, &self.notified)
+            .and_then(|threshold_val| self.notified.get(&threshold_val))
+            .ok_or(format!("no threshold at or above {}%", level))?;
+        let event = self.hook_event("test", level, Some(notification.level));
+        self.send_notification(notification, &event)
+            .map_err(|err| err.to_string())
+    }
+
+    fn is_snoozed(&self) -> bool {
+        self.snoozed_until
+            .is_some_and(|until| state::now_secs() < until)
+    }
+
+    fn status(&self) -> Result<DaemonStatus, Error> {
+        let level = read_level(&self.battery_dir)?;
+        let status = read_status(&self.battery_dir)?;
+        let time_remaining =
+            get_time_remaining(&self.battery_dir, status == ChargingStatus::Charging);
+        Ok(DaemonStatus {
+            level,
+            status,
+            battery: self
+                .battery
+                .as_deref()
+                .unwrap_or(DEFAULT_BATTERY)
+                .to_string(),
+            active_threshold: find_lowest_threshold(level, &self.notified),
+            snoozed_until: self.snoozed_until.filter(|_| self.is_snoozed()),
+            time_remaining_secs: time_remaining.map(|remaining| remaining.as_secs()),
+        })
+    }
+
+    /// Update the D-Bus properties and send the status to all subscribers if the level, charging
+    /// status, active threshold or snooze changed since it was last sent
+    fn publish_status(&mut self) {
+        if self.subscribers.is_empty() && self.dbus.is_none() {
+            return;
+        }
+        let Ok(status) = self.status() else {
+            return;
+        };
+        if let Some(dbus) = &self.dbus {
+            if let Err(err) = dbus.update(&status) {
+                eprintln!("Could not update D-Bus properties: {}", err);
+            }
+        }
+        let unchanged = self.last_published.as_ref().is_some_and(|last| {
+            last.level == status.level
+                && last.status == status.status
+                && last.active_threshold == status.active_threshold
+                && last.snoozed_until == status.snoozed_until
+        });
+        if unchanged {
+            return;
+        }
+        // subscribers whose connection was closed are dropped
+        self.subscribers.retain(|subscriber| {
+            subscriber
+                .try_send(Response::status(status.clone()))
+                .is_ok()
+        });
+        self.last_published = Some(status);
+    }
+
+    /// Log the drain during the suspend and record it in the history, notify about it if enabled
+    /// or if the battery drained faster than the configured maximum
+    fn report_resume(&self, suspended: &SuspendRecord) {
+        let Ok(level) = read_level(&self.battery_dir) else {
+            return;
+        };
+        let entry = SuspendEntry {
+            suspended_at: suspended.at,
+            resumed_at: state::now_secs(),
+            start_level: suspended.level,
+            end_level: level,
+            start_energy_wh: suspended.energy_wh,
+            end_energy_wh: get_energy_wh(&self.battery_dir),
+        };
+        let rate = entry
+            .percent_per_hour()
+            .map_or(String::new(), |drain| format!(" ({:.1}%/h)", drain));
+        let message = format!(
+            "Battery dropped from {}% to {}% while suspended for {}{}",
+            entry.start_level,
+            entry.end_level,
+            format_duration(entry.duration()),
+            rate
+        );
+        eprintln!("{}", message);
+        self.record_suspend(&entry);
+
+        let suspend = &self.config.suspend;
+        let too_high = entry.duration() >= MIN_CHECKED_SUSPEND
+            && entry
+                .percent_per_hour()
+                .zip(suspend.max_percent_per_hour)
+                .is_some_and(|(drain, max)| drain > max);
+        if level >= suspended.level || !(suspend.notify_on_resume || too_high) {
+            return;
+        }
+        let summary = if too_high {
+            "High drain during suspend"
+        } else {
+            "Battery Status"
+        };
+        if let Err(err) = self.send_message(summary, &message, &suspend.urgency) {
+            eprintln!("{}", err);
+        }
+    }
+
+    fn record_suspend(&self, entry: &SuspendEntry) {
+        let history = &self.config.history;
+        if !history.enabled {
+            return;
+        }
+        let Some(dir) = history::get_history_dir() else {
+            return;
+        };
+        let battery = self.battery.as_deref();
+        if let Err(err) = history::append_suspend(&dir, battery, entry, history.max_size_kb * 1024)
+        {
+            eprintln!("Could not record suspend in the history, error: {}", err);
+        }
+    }
+
+    /// Start a new session when the charger was connected or disconnected and send the summary of
+    /// the session that ended
+    fn track_session(&mut self, level: u32, status: ChargingStatus) {
+        if status == ChargingStatus::Unknown {
+            return;
+        }
+        let plugged_in = status.is_plugged_in();
+        if self
+            .session
+            .as_ref()
+            .is_some_and(|session| session.plugged_in == plugged_in)
+        {
+            return;
+        }
+
+        let now = state::now_secs();
+        let energy_wh = get_energy_wh(&self.battery_dir);
+        let ended = self.session.replace(Session {
+            plugged_in,
+            start_level: level,
+            started_at: now,
+            start_energy_wh: energy_wh,
+        });
+        if !self.config.sessions.notify_summary {
+            return;
+        }
+        let Some(summary) = ended.and_then(|session| session.summary(level, energy_wh, now)) else {
+            return;
+        };
+        if let Err(err) =
+            self.send_message("Battery Status", &summary, &self.config.sessions.urgency)
+        {
+            eprintln!("{}", err);
+        }
+    }
+
+    /// Warn if the battery discharges faster than configured
+    fn check_drain(&mut self, level: u32, status: ChargingStatus) {
+        let config = &self.config.drain;
+        if !config.is_enabled() {
+            return;
+        }
+        if status != ChargingStatus::Discharging {
+            self.drain.clear();
+            return;
+        }
+        let energy_wh = get_energy_wh(&self.battery_dir);
+        let Some(message) = self
+            .drain
+            .record(config, state::now_secs(), level, energy_wh)
+        else {
+            return;
+        };
+        if let Err(err) = self.send_message("Battery draining fast", &message, &config.urgency) {
+            eprintln!("{}", err);
+        }
+    }
+
+    /// Warn if AC is connected but the battery is discharging or charging too slowly
+    fn check_charger(&mut self, level: u32, status: ChargingStatus) {
+        let config = &self.config.charger;
+        if !config.enabled {
+            return;
+        }
+        let supply_watts = config.min_watts.and_then(|_| get_usb_supply_watts());
+        let Some(message) = self.charger.record(
+            config,
+            state::now_secs(),
+            level,
+            status,
+            is_ac_online(),
+            supply_watts,
+        ) else {
+            return;
+        };
+        if let Err(err) = self.send_message("Charger insufficient", &message, &config.urgency) {
+            eprintln!("{}", err);
+        }
+    }
+
+    /// Notify the hottest temperature threshold the battery reached, thresholds are not snoozed
+    fn check_temperature(&mut self, level: u32, status: ChargingStatus) {
+        if self.config.temperature.notifications.is_empty() {
+            return;
+        }
+        let Some(temperature_c) = get_temperature_c(&self.battery_dir) else {
+            return;
+        };
+        let Some(notification) = self.config.temperature.check(temperature_c, status) else {
+            return;
+        };
+        let mut notification = notification.clone();
+        notification
+            .title
+            .get_or_insert_with(|| temperature::DEFAULT_TITLE.to_string());
+        notification
+            .message
+            .get_or_insert_with(|| temperature::DEFAULT_MESSAGE.to_string());
+        let event = self.hook_event("temperature", level, Some(notification.level));
+        if let Err(err) = self.send_notification(&notification, &event) {
+            eprintln!("{}", err);
+        }
+        (self.run_commands)(vec![(notification.commands, event)], &self.config.hooks);
+    }
+
+    fn record_sample(&self, level: u32, status: ChargingStatus) {
+        let history = &self.config.history;
+        if !history.enabled {
+            return;
+        }
+        let Some(dir) = history::get_history_dir() else {
+            return;
+        };
+        let battery = self.battery.as_deref();
+        let sample = history::Sample::read(&self.battery_dir, state::now_secs(), level, status);
+        if let Err(err) = history::append(&dir, battery, &sample, history.max_size_kb * 1024) {
+            eprintln!("Could not record battery history, error: {}", err);
+        }
+    }
+
+    /// Notify once the battery is full or held at its charge limit and run the commands of the full
+    /// notification
+    fn check_full_battery(&mut self, level: u32) {
+        let event = self.hook_event("full", level, None);
+        let full = &mut self.config.full_notification;
+        let Some(message) = check_notify_full_battery(
+            level,
+            self.last_battery_level.unwrap_or(level),
+            full,
+            event.status,
+            get_charge_limit(&self.battery_dir),
+        ) else {
+            return;
+        };
+        let full = &self.config.full_notification;
+        let title = full.title.as_deref().unwrap_or("Battery Status");
+        let snapshot = read_snapshot(&self.battery_dir, level, event.status, None);
+        (self.run_commands)(vec![(full.commands.clone(), event)], &self.config.hooks);
+        let result = (self.notify)(
+            &template::render(title, &snapshot),
+            &template::render(&message, &snapshot),
+            &full.urgency,
+            None,
+        );
+        if let Err(err) = result {
+            eprintln!("{}", err);
+        }
+    }
+
+    /// Notify once when the full capacity fell below the configured percentage of the design
+    /// capacity
+    fn check_health(&mut self) {
+        let Some(notify_below) = self.config.health.notify_below else {
+            return;
+        };
+        if self.health_notified {
+            return;
+        }
+        let Some(health) = get_health_percent(&self.battery_dir) else {
+            return;
+        };
+        if health >= notify_below {
+            return;
+        }
+
+        self.health_notified = true;
+        let message = format!(
+            "The battery holds {}% of its design capacity, consider replacing it",
+            health
+        );
+        if let Err(err) = self.send_message("Battery Health", &message, &self.config.health.urgency)
+        {
+            eprintln!("{}", err);
+        }
+    }
+
+    /// Read the battery level and send all due notifications. Returns how long to wait until the
+    /// next tick.
+    fn tick(&mut self) -> Duration {
+        let level = match read_level(&self.battery_dir) {
+            Ok(level) => {
+                self.read_failures = 0;
+                level
+            }
+            Err(err) => {
+                self.read_failures += 1;
+                eprintln!("Could not read battery level: {}", err);
+                if self.read_failures == SENSOR_FAILURES_BEFORE_NOTIFICATION {
+                    if let Err(err) = self.send_message(
+                        "Battery sensor unavailable",
+                        "The battery level could not be read, battery notifications are paused",
+                        &Urgency::Normal,
+                    ) {
+                        eprintln!("{}", err);
+                    }
+                }
+                return read_failure_backoff(self.read_failures);
+            }
+        };
+
+        let mut crossed = find_crossed_thresholds(level, self.last_battery_level, &self.notified);
+        // thresholds limited to other charging statuses stay due until they apply
+        let status = read_status(&self.battery_dir).unwrap_or(ChargingStatus::Unknown);
+        crossed.retain(|threshold_val| {
+            self.notified
+                .get(threshold_val)
+                .is_some_and(|notification| notification.applies_to(status))
+        });
+        if let Some(dbus) = &self.dbus {
+            for threshold_val in &crossed {
+                if let Err(err) = dbus.threshold_crossed(*threshold_val, level) {
+                    eprintln!("Could not emit ThresholdCrossed: {}", err);
+                }
+            }
+        }
+        if let Some((&most_severe, skipped)) = crossed.split_last() {
+            // the commands of all crossed thresholds run on one thread, the most severe last
+            let mut commands = skipped
+                .iter()
+                .filter_map(|threshold_val| self.notify_skipped_threshold(level, *threshold_val))
+                .collect::<Vec<_>>();
+            let event = self.hook_event("threshold", level, Some(most_severe));
+            if let Some(notification) = self.notified.get(&most_severe) {
+                if !self.is_snoozed() {
+                    if let Err(err) = self.send_notification(notification, &event) {
+                        eprintln!("{}", err);
+                    }
+                }
+                commands.push((notification.commands.clone(), event));
+            }
+            if let Some(notification) = self.notified.get_mut(&most_severe) {
+                notification.notified = true;
+                self.notified_at.insert(most_severe, state::now_secs());
+            }
+            (self.run_commands)(commands, &self.config.hooks);
+            self.start_threshold_action(&crossed);
+        }
+
+        if let Some(threshold_val) = find_lowest_threshold(level, &self.notified) {
+            reset_other_notifications(&threshold_val, &mut self.notified);
+            self.notified_at.retain(|level, _| *level == threshold_val);
+        }
+
+        // the full notification is checked again once a snooze is over
+        if !self.is_snoozed() {
+            self.check_full_battery(level);
+        }
+
+        self.track_session(level, status);
+        self.check_drain(level, status);
+        self.check_charger(level, status);
+        self.check_temperature(level, status);
+        self.check_health();
+        self.record_sample(level, status);
+        self.last_battery_level = Some(level);
+        self.save_state();
+        self.publish_status();
+
+        POLL_INTERVAL
+    }
+}
+
+/// Move the notifications out of the config into a map by their threshold level
+fn notifications_by_level(config: &mut Config) -> HashMap<u32, Notification> {
+    config
+        .notifications
+        .drain(..)
+        .map(|notification| (notification.level, notification))
+        .collect()
+}
+
+/// Delay before reading the battery again after failures, doubles with each failure up to the
+/// regular polling interval
+fn read_failure_backoff(failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    INITIAL_READ_RETRY_DELAY
+        .saturating_mul(factor)
+        .min(POLL_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use std::cell::RefCell;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    thread_local! {
+        // titles and messages of the notifications sent on the current test's thread
+        static SENT: RefCell<Vec<(String, String)>> = const { RefCell::new(vec![]) };
+        // command lines run on the current test's thread in the order they would run
+        static RUN: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+        static STARTED: RefCell<Vec<PowerAction>> = const { RefCell::new(vec![]) };
+    }
+
+    fn record(title: &str, message: &str, _: &Urgency, _: Option<u32>) -> Result<(), Error> {
+        SENT.with(|sent| {
+            sent.borrow_mut()
+                .push((title.to_string(), message.to_string()))
+        });
+        Ok(())
+    }
+
+    fn take_sent() -> Vec<(String, String)> {
+        SENT.with(|sent| sent.take())
+    }
+
+    fn record_commands(lists: Vec<(Vec<CommandSpec>, HookEvent)>, _: &HooksConfig) {
+        let lines = lists
+            .iter()
+            .flat_map(|(commands, _)| commands.iter().map(|command| command.run().to_string()));
+        RUN.with(|run| run.borrow_mut().extend(lines));
+    }
+
+    fn record_countdown(
+        action: PowerAction,
+        _: Duration,
+        _: Option<&Battery>,
+    ) -> thread::JoinHandle<()> {
+        STARTED.with(|started| started.borrow_mut().push(action));
+        thread::spawn(|| {})
+    }
+
+    /// Daemon with the default thresholds for a discharging battery at 25% in a temporary dir
+    fn test_daemon(name: &str) -> (Daemon, TestDir) {
+        let dir = TestDir::new(&format!("daemon-{}", name));
+        let battery_dir = dir.join(DEFAULT_BATTERY);
+        std::fs::create_dir_all(&battery_dir).unwrap();
+        std::fs::write(battery_dir.join("capacity"), "25\n").unwrap();
+        std::fs::write(battery_dir.join("status"), "Discharging\n").unwrap();
+
+        let mut daemon = Daemon::new(config::get_default_config(), None, None);
+        daemon.battery_dir = battery_dir;
+        daemon.state_path = Some(dir.join("state.json"));
+        daemon.notify = record;
+        daemon.run_commands = record_commands;
+        daemon.start_countdown = record_countdown;
+        daemon.config.history.enabled = false;
+        (daemon, dir)
+    }
+
+    #[test]
+    fn control_requests_round_trip() {
+        let (mut daemon, dir) = test_daemon("control");
+        let mut config = config::get_default_config();
+        config.notifications = vec![Notification {
+            level: 50,
+            ..Default::default()
+        }];
+        let config_file = dir.join("config.json");
+        std::fs::write(&config_file, serde_json::to_string(&config).unwrap()).unwrap();
+        daemon.config_file = Some(config_file);
+
+        let socket = dir.join("powernotd.sock");
+        control::listen(&socket, daemon.event_sender()).unwrap();
+        let requests = ["status", "snooze 30m", "status", "reload", "status"];
+        let client = thread::spawn(move || {
+            let mut stream = UnixStream::connect(&socket).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            requests.map(|line| {
+                stream.write_all(format!("{}\n", line).as_bytes()).unwrap();
+                let mut response = String::new();
+                reader.read_line(&mut response).unwrap();
+                serde_json::from_str::<Response>(&response).unwrap()
+            })
+        });
+        for _ in requests {
+            let event = daemon.events.recv().unwrap();
+            assert!(!daemon.handle_event(event));
+        }
+        let [status, snooze, snoozed, reload, reloaded] = client.join().unwrap();
+
+        let status = status.status.unwrap();
+        assert_eq!(status.level, 25);
+        assert_eq!(status.status, ChargingStatus::Discharging);
+        assert_eq!(status.active_threshold, Some(30));
+        assert_eq!(status.snoozed_until, None);
+        assert!(snooze.ok);
+        let snoozed_until = snoozed.status.unwrap().snoozed_until;
+        assert!(snoozed_until.is_some());
+        // the reloaded thresholds are used right away while the snooze is kept
+        assert!(reload.ok);
+        let reloaded = reloaded.status.unwrap();
+        assert_eq!(reloaded.active_threshold, Some(50));
+        assert_eq!(reloaded.snoozed_until, snoozed_until);
+    }
+
+    #[test]
+    fn stale_or_missing_state_is_not_restored() {
+        let (mut daemon, _dir) = test_daemon("restore");
+        assert!(!daemon.restore_state());
+
+        let path = daemon.state_path.clone().unwrap();
+        let save = |saved_at| {
+            let state = State {
+                saved_at,
+                last_level: 27,
+                notified: [(30, saved_at)].into(),
+                full_notified: false,
+                health_notified: true,
+            };
+            state::save(&path, &state).unwrap();
+        };
+        save(state::now_secs() - state::MAX_STATE_AGE.as_secs() - 60);
+        assert!(!daemon.restore_state());
+        // only the one-time health notification is kept from a stale state
+        assert!(daemon.health_notified);
+        assert_eq!(daemon.last_battery_level, None);
+        assert!(!daemon.notified[&30].notified);
+
+        save(state::now_secs());
+        assert!(daemon.restore_state());
+        assert_eq!(daemon.last_battery_level, Some(27));
+        assert!(daemon.notified[&30].notified);
+    }
+
+    #[test]
+    fn startup_behaviors() {
+        let (mut daemon, _dir) = test_daemon("startup");
+        let mut startup = |behavior| {
+            daemon.config.startup = behavior;
+            daemon.last_battery_level = None;
+            daemon.notified.get_mut(&30).unwrap().notified = false;
+            daemon.startup();
+            let notified = daemon.notified[&30].notified;
+            (daemon.last_battery_level, notified, take_sent())
+        };
+
+        assert_eq!(
+            startup(StartupBehavior::NotifyCurrent),
+            (None, false, vec![])
+        );
+        assert_eq!(startup(StartupBehavior::Silent), (Some(25), true, vec![]));
+        let summary = ("Battery Status".to_string(), "25%, discharging".to_string());
+        assert_eq!(
+            startup(StartupBehavior::Summary),
+            (Some(25), true, vec![summary])
+        );
+    }
+
+    #[test]
+    fn health_is_notified_once() {
+        let (mut daemon, _dir) = test_daemon("health");
+        std::fs::write(daemon.battery_dir.join("energy_full"), "70000000\n").unwrap();
+        std::fs::write(daemon.battery_dir.join("energy_full_design"), "100000000\n").unwrap();
+        daemon.config.health.notify_below = Some(80);
+
+        daemon.check_health();
+        daemon.check_health();
+        let sent = take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "Battery Health");
+        assert!(sent[0].1.contains("70%"));
+        assert!(daemon.health_notified);
+    }
+
+    /// Let the battery drop from 31% to 9% in one tick, crossing the thresholds 30, 20, 15 and 10.
+    /// Returns the messages sent, the commands run and the power actions started.
+    fn drop_to_9(
+        name: &str,
+        policy: CrossingPolicy,
+    ) -> (Vec<String>, Vec<String>, Vec<PowerAction>) {
+        let (mut daemon, _dir) = test_daemon(name);
+        daemon.config.crossing_policy = policy;
+        for notification in daemon.notified.values_mut() {
+            notification.message = Some("{threshold}%".to_string());
+            notification.commands = vec![CommandSpec::Line(format!("echo {}", notification.level))];
+        }
+        daemon.notified.get_mut(&20).unwrap().action = Some(PowerAction::Suspend);
+        daemon.notified.get_mut(&15).unwrap().action = Some(PowerAction::Hibernate);
+        daemon.last_battery_level = Some(31);
+        std::fs::write(daemon.battery_dir.join("capacity"), "9\n").unwrap();
+
+        daemon.tick();
+        let messages = take_sent()
+            .into_iter()
+            .map(|(_, message)| message)
+            .collect();
+        let commands = RUN.with(|run| run.take());
+        let started = STARTED.with(|started| started.take());
+        (messages, commands, started)
+    }
+
+    #[test]
+    fn most_severe_policy_skips_crossed_thresholds() {
+        let (messages, commands, started) = drop_to_9("most-severe", CrossingPolicy::MostSevere);
+        assert_eq!(messages, ["10%"]);
+        assert_eq!(commands, ["echo 10"]);
+        // only the action of the most severe threshold itself is considered
+        assert_eq!(started, []);
+    }
+
+    #[test]
+    fn all_in_order_policy_notifies_every_crossed_threshold() {
+        let (messages, commands, started) = drop_to_9("all-in-order", CrossingPolicy::AllInOrder);
+        assert_eq!(messages, ["30%", "20%", "15%", "10%"]);
+        assert_eq!(commands, ["echo 30", "echo 20", "echo 15", "echo 10"]);
+        assert_eq!(started, [PowerAction::Hibernate]);
+    }
+
+    #[test]
+    fn all_commands_policy_only_notifies_most_severe_threshold() {
+        let (messages, commands, started) = drop_to_9(
+            "all-commands",
+            CrossingPolicy::AllCommandsMostSevereNotification,
+        );
+        assert_eq!(messages, ["10%"]);
+        assert_eq!(commands, ["echo 30", "echo 20", "echo 15", "echo 10"]);
+        assert_eq!(started, [PowerAction::Hibernate]);
+    }
+
+    #[test]
+    fn full_notification_at_charge_limit() {
+        let (mut daemon, _dir) = test_daemon("charge-limit");
+        std::fs::write(daemon.battery_dir.join("status"), "Charging\n").unwrap();
+        daemon.last_battery_level = Some(79);
+
+        daemon.check_full_battery(80);
+        assert_eq!(take_sent(), []);
+
+        std::fs::write(
+            daemon.battery_dir.join("charge_control_end_threshold"),
+            "80\n",
+        )
+        .unwrap();
+        daemon.check_full_battery(80);
+        let sent = take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1, "Charged to 80%, limit reached");
+    }
+
+    #[test]
+    fn temperature_is_notified_once_per_threshold_until_cooled_down() {
+        let (mut daemon, _dir) = test_daemon("temperature");
+        let threshold = |level, urgency| Notification {
+            level,
+            urgency,
+            ..Default::default()
+        };
+        daemon.config.temperature.notifications = vec![
+            threshold(45, Urgency::Normal),
+            threshold(55, Urgency::Critical),
+        ];
+        let mut check = |decidegrees: &str| {
+            std::fs::write(daemon.battery_dir.join("temp"), decidegrees).unwrap();
+            daemon.check_temperature(25, ChargingStatus::Discharging);
+            take_sent()
+                .into_iter()
+                .map(|(_, message)| message)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(check("460"), ["Battery at 46 °C while discharging"]);
+        assert_eq!(check("470"), [] as [&str; 0]);
+        assert_eq!(check("560"), ["Battery at 56 °C while discharging"]);
+        assert_eq!(check("500"), [] as [&str; 0]);
+        // cooling down below 43 °C resets the warning
+        assert_eq!(check("400"), [] as [&str; 0]);
+        assert_eq!(check("460"), ["Battery at 46 °C while discharging"]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_io::block_on;
+use zbus::blocking::{connection, Connection};
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+use crate::control::Request;
+use crate::daemon::{DaemonStatus, Event};
+use crate::ChargingStatus;
+
+pub const BUS_NAME: &str = "org.powernotd.Daemon1";
+pub const OBJECT_PATH: &str = "/org/powernotd/Daemon1";
+
+/// Latest status of the daemon, shared outside of the interface so updating it does not wait for
+/// method calls that are waiting for the daemon
+type SharedStatus = Arc<Mutex<Option<DaemonStatus>>>;
+
+/// The org.powernotd.Daemon1 interface. D-Bus has no optional values so unknown or unset
+/// properties are 0.
+struct DaemonInterface {
+    status: SharedStatus,
+    sender: Sender<Event>,
+}
+
+impl DaemonInterface {
+    fn with_status<T>(&self, f: impl FnOnce(&DaemonStatus) -> T) -> Option<T> {
+        self.status.lock().unwrap().as_ref().map(f)
+    }
+
+    /// Pass the request to the daemon and wait for its response without blocking the executor
+    async fn request(&self, request: Request) -> fdo::Result<()> {
+        let (reply, response) = async_channel::bounded(1);
+        self.sender
+            .send(Event::Control(request, reply))
+            .map_err(|_| fdo::Error::Failed("daemon is not running".to_string()))?;
+        let response = response
+            .recv()
+            .await
+            .map_err(|_| fdo::Error::Failed("daemon did not respond".to_string()))?;
+        match response.error {
+            Some(error) => Err(fdo::Error::Failed(error)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[zbus::interface(name = "org.powernotd.Daemon1")]
+impl DaemonInterface {
+    /// Load the config-file again
+    async fn reload(&self) -> fdo::Result<()> {
+        self.request(Request::Reload).await
+    }
+
+    /// Do not show threshold notifications for the given seconds, zero ends a snooze
+    async fn snooze(&self, secs: u64) -> fdo::Result<()> {
+        self.request(Request::Snooze(Duration::from_secs(secs)))
+            .await
+    }
+
+    /// Show the notification of the threshold for the given level without running its commands
+    async fn test_notification(&self, level: u32) -> fdo::Result<()> {
+        self.request(Request::Test(level)).await
+    }
+
+    #[zbus(property)]
+    fn level(&self) -> u32 {
+        self.with_status(|status| status.level).unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> String {
+        self.with_status(|status| status.status)
+            .unwrap_or(ChargingStatus::Unknown)
+            .to_string()
+    }
+
+    /// Estimated seconds until the battery is empty or full
+    #[zbus(property)]
+    fn time_remaining(&self) -> u64 {
+        self.with_status(|status| status.time_remaining_secs)
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    fn active_threshold(&self) -> u32 {
+        self.with_status(|status| status.active_threshold)
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    /// Unix timestamp until which notifications are snoozed
+    #[zbus(property)]
+    fn snoozed_until(&self) -> u64 {
+        self.with_status(|status| status.snoozed_until)
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    /// Emitted when the battery level falls below a threshold, also while snoozed
+    #[zbus(signal)]
+    async fn threshold_crossed(
+        emitter: &SignalEmitter<'_>,
+        threshold: u32,
+        level: u32,
+    ) -> zbus::Result<()>;
+}
+
+/// The daemon's object exported on a D-Bus connection
+pub struct Service {
+    connection: Connection,
+    status: SharedStatus,
+}
+
+impl Service {
+    /// Export the daemon on the session bus under the name org.powernotd.Daemon1, fails if
+    /// another daemon already owns the name
+    pub fn start(sender: Sender<Event>) -> zbus::Result<Service> {
+        Service::build(connection::Builder::session()?.name(BUS_NAME)?, sender)
+    }
+
+    /// Build the connection with the daemon object exported on it. The object is exported while
+    /// building so method calls arriving right after are not lost.
+    pub fn build(builder: connection::Builder, sender: Sender<Event>) -> zbus::Result<Service> {
+        let status = SharedStatus::default();
+        let interface = DaemonInterface {
+            status: status.clone(),
+            sender,
+        };
+        let connection = builder.serve_at(OBJECT_PATH, interface)?.build()?;
+        Ok(Service { connection, status })
+    }
+
+    /// Update the properties and emit PropertiesChanged for the ones that changed
+    pub fn update(&self, status: &DaemonStatus) -> zbus::Result<()> {
+        let interface_ref = self
+            .connection
+            .object_server()
+            .interface::<_, DaemonInterface>(OBJECT_PATH)?;
+        let previous = self.status.lock().unwrap().replace(status.clone());
+        let interface = interface_ref.get();
+        let emitter = interface_ref.signal_emitter();
+
+        let changed = |field: fn(&DaemonStatus) -> Option<u64>| {
+            previous.as_ref().and_then(field) != field(status)
+        };
+        if changed(|status| Some(status.level.into())) {
+            block_on(interface.level_changed(emitter))?;
+        }
+        if previous.as_ref().map(|previous| &previous.status) != Some(&status.status) {
+            block_on(interface.status_changed(emitter))?;
+        }
+        if changed(|status| status.time_remaining_secs) {
+            block_on(interface.time_remaining_changed(emitter))?;
+        }
+        if changed(|status| status.active_threshold.map(u64::from)) {
+            block_on(interface.active_threshold_changed(emitter))?;
+        }
+        if changed(|status| status.snoozed_until) {
+            block_on(interface.snoozed_until_changed(emitter))?;
+        }
+        Ok(())
+    }
+
+    pub fn threshold_crossed(&self, threshold: u32, level: u32) -> zbus::Result<()> {
+        let emitter = SignalEmitter::new(self.connection.inner(), OBJECT_PATH)?;
+        block_on(DaemonInterface::threshold_crossed(
+            &emitter, threshold, level,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Response;
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc;
+
+    #[zbus::proxy(
+        interface = "org.powernotd.Daemon1",
+        default_service = "org.powernotd.Daemon1",
+        default_path = "/org/powernotd/Daemon1",
+        gen_async = false
+    )]
+    trait Daemon1 {
+        fn snooze(&self, secs: u64) -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn level(&self) -> zbus::Result<u32>;
+
+        #[zbus(property)]
+        fn active_threshold(&self) -> zbus::Result<u32>;
+    }
+
+    #[test]
+    fn service_exports_status_and_forwards_requests() {
+        let (server_stream, client_stream) = UnixStream::pair().unwrap();
+        let (sender, events) = mpsc::channel();
+        let server = std::thread::spawn(move || {
+            let builder = connection::Builder::async_io_unix_stream(server_stream)
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .p2p();
+            Service::build(builder, sender).unwrap()
+        });
+        let client = connection::Builder::async_io_unix_stream(client_stream)
+            .p2p()
+            .build()
+            .unwrap();
+        let service = server.join().unwrap();
+
+        service
+            .update(&DaemonStatus {
+                level: 42,
+                status: ChargingStatus::Discharging,
+                battery: "BAT0".to_string(),
+                active_threshold: Some(50),
+                snoozed_until: None,
+                time_remaining_secs: None,
+            })
+            .unwrap();
+        let proxy = Daemon1Proxy::new(&client).unwrap();
+        assert_eq!(proxy.level().unwrap(), 42);
+        assert_eq!(proxy.active_threshold().unwrap(), 50);
+
+        let daemon = std::thread::spawn(move || match events.recv().unwrap() {
+            Event::Control(request, reply) => {
+                reply.try_send(Response::ok()).unwrap();
+                request
+            }
+            event => panic!("unexpected event {:?}", event),
+        });
+        proxy.snooze(600).unwrap();
+        assert_eq!(
+            daemon.join().unwrap(),
+            Request::Snooze(Duration::from_secs(600))
+        );
+    }
+}
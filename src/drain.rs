@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::duration::format_duration;
+use crate::notification::Urgency;
+
+/// Warning about an unusually high discharge rate, disabled unless a maximum is set
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DrainConfig {
+    // warn when the average power draw over the window exceeds this many watts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_watts: Option<f64>,
+    // warn when the level drops faster than this many percent per minute over the window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_percent_per_minute: Option<f64>,
+    // seconds over which the discharge rate is averaged
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    // seconds after a warning before the next one is sent
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+    #[serde(default)]
+    pub urgency: Urgency,
+}
+
+fn default_window_secs() -> u64 {
+    5 * 60
+}
+
+fn default_cooldown_secs() -> u64 {
+    30 * 60
+}
+
+impl Default for DrainConfig {
+    fn default() -> Self {
+        DrainConfig {
+            max_watts: None,
+            max_percent_per_minute: None,
+            window_secs: default_window_secs(),
+            cooldown_secs: default_cooldown_secs(),
+            urgency: Urgency::default(),
+        }
+    }
+}
+
+impl DrainConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_watts.is_some() || self.max_percent_per_minute.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DrainSample {
+    timestamp: u64,
+    level: u32,
+    energy_wh: Option<f64>,
+}
+
+/// Discharge samples within the sliding window and the time of the last warning
+#[derive(Debug, Default)]
+pub struct DrainMonitor {
+    samples: VecDeque<DrainSample>,
+    last_warning: Option<u64>,
+}
+
+impl DrainMonitor {
+    /// Forget the samples, called while the battery is not discharging
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Add a sample taken while discharging and return the warning message if the rate over the
+    /// window exceeds a maximum and the cooldown is over
+    pub fn record(
+        &mut self,
+        config: &DrainConfig,
+        timestamp: u64,
+        level: u32,
+        energy_wh: Option<f64>,
+    ) -> Option<String> {
+        let window_start = timestamp.saturating_sub(config.window_secs);
+        self.samples
+            .retain(|sample| sample.timestamp >= window_start && sample.timestamp < timestamp);
+        self.samples.push_back(DrainSample {
+            timestamp,
+            level,
+            energy_wh,
+        });
+
+        // the rate is not meaningful until at least half of the window is covered
+        let oldest = self.samples.front()?;
+        let elapsed = timestamp - oldest.timestamp;
+        if elapsed == 0 || elapsed * 2 < config.window_secs {
+            return None;
+        }
+        let cooling_down = self
+            .last_warning
+            .is_some_and(|last| timestamp.saturating_sub(last) < config.cooldown_secs);
+        if cooling_down {
+            return None;
+        }
+
+        let hours = elapsed as f64 / 3600.0;
+        let watts = oldest
+            .energy_wh
+            .zip(energy_wh)
+            .map(|(start, end)| (start - end) / hours);
+        let percent_per_minute = (oldest.level as f64 - level as f64) / (elapsed as f64 / 60.0);
+        let too_high = watts
+            .zip(config.max_watts)
+            .is_some_and(|(watts, max)| watts > max)
+            || config
+                .max_percent_per_minute
+                .is_some_and(|max| percent_per_minute > max);
+        if !too_high {
+            return None;
+        }
+
+        self.last_warning = Some(timestamp);
+        let mut message = match watts {
+            Some(watts) => format!(
+                "Discharging at {:.1} W ({:.1}%/min)",
+                watts, percent_per_minute
+            ),
+            None => format!("Discharging at {:.1}%/min", percent_per_minute),
+        };
+        let runtime_secs = match (watts, energy_wh) {
+            (Some(watts), Some(energy_wh)) if watts > 0.0 => energy_wh / watts * 3600.0,
+            _ if percent_per_minute > 0.0 => level as f64 / percent_per_minute * 60.0,
+            _ => return Some(message),
+        };
+        message.push_str(&format!(
+            ", about {} remaining",
+            format_duration(Duration::from_secs_f64(runtime_secs))
+        ));
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warns_about_high_drain_once_per_cooldown() {
+        let config = DrainConfig {
+            max_watts: Some(25.0),
+            ..Default::default()
+        };
+        let mut monitor = DrainMonitor::default();
+        // 30 Wh used in an hour are 30 W, sampled every minute
+        let energy = |minute: u64| 60.0 - minute as f64 * 0.5;
+        for minute in 0..2 {
+            let warning = monitor.record(&config, minute * 60, 90, Some(energy(minute)));
+            assert_eq!(warning, None);
+        }
+        let warning = monitor.record(&config, 180, 88, Some(energy(3)));
+        assert_eq!(
+            warning.as_deref(),
+            Some("Discharging at 30.0 W (0.7%/min), about 1h57m remaining")
+        );
+        assert_eq!(monitor.record(&config, 240, 88, Some(energy(4))), None);
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A power supply attribute could not be read, e.g. because the battery was removed
+    ReadAttribute {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A power supply attribute contained an unexpected value
+    ParseAttribute { path: PathBuf, contents: String },
+    /// The notification could not be delivered to the notification server
+    Notification(notify_rust::error::Error),
+    /// A configured command could not be run or did not succeed
+    Command { command: String, reason: String },
+    /// A power supply attribute could not be written, e.g. because of missing permissions
+    WriteAttribute {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The charge limit is invalid, not supported or was not accepted by the firmware
+    ChargeLimit(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ReadAttribute { path, source } => {
+                write!(f, "could not read {}: {}", path.to_string_lossy(), source)
+            }
+            Error::ParseAttribute { path, contents } => write!(
+                f,
+                "unexpected contents '{}' in {}",
+                contents,
+                path.to_string_lossy()
+            ),
+            Error::Notification(err) => write!(f, "could not send notification: {}", err),
+            Error::Command { command, reason } => {
+                write!(f, "could not run command '{}': {}", command, reason)
+            }
+            Error::WriteAttribute { path, source } => {
+                write!(f, "could not write {}: {}", path.to_string_lossy(), source)
+            }
+            Error::ChargeLimit(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<notify_rust::error::Error> for Error {
+    fn from(value: notify_rust::error::Error) -> Self {
+        Error::Notification(value)
+    }
+}
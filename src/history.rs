@@ -0,0 +1,354 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::battery::{get_energy_wh, get_power_w, is_ac_online};
+use crate::duration::{format_duration, format_timestamp};
+use crate::state::get_state_dir;
+use crate::{Battery, ChargingStatus, DEFAULT_BATTERY};
+
+const HISTORY_DIR_NAME: &str = "history";
+
+/// Levels of the sparkline from empty to full
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistoryConfig {
+    // record a sample on every check of the battery
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    // size after which the log is rotated, the previous log is kept so up to twice the size is used
+    #[serde(default = "default_max_size_kb")]
+    pub max_size_kb: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_max_size_kb() -> u64 {
+    1024
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: default_enabled(),
+            max_size_kb: default_max_size_kb(),
+        }
+    }
+}
+
+/// State of the battery at one point in time, written as one line of json per sample
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sample {
+    // unix timestamp in seconds
+    pub timestamp: u64,
+    pub level: u32,
+    pub status: ChargingStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub energy_wh: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_w: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ac_online: Option<bool>,
+}
+
+impl Sample {
+    /// Sample the battery in dir with the already known level and status
+    pub fn read(dir: &Path, timestamp: u64, level: u32, status: ChargingStatus) -> Sample {
+        Sample {
+            timestamp,
+            level,
+            status,
+            energy_wh: get_energy_wh(dir),
+            power_w: get_power_w(dir),
+            ac_online: is_ac_online(),
+        }
+    }
+}
+
+/// Battery state before and after one suspend of the system
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SuspendEntry {
+    // unix timestamps in seconds
+    pub suspended_at: u64,
+    pub resumed_at: u64,
+    pub start_level: u32,
+    pub end_level: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_energy_wh: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_energy_wh: Option<f64>,
+}
+
+impl SuspendEntry {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.resumed_at.saturating_sub(self.suspended_at))
+    }
+
+    /// Percent of the battery lost per hour of suspend, None if no time passed
+    pub fn percent_per_hour(&self) -> Option<f64> {
+        let hours = self.duration().as_secs_f64() / 3600.0;
+        let lost = self.start_level as f64 - self.end_level as f64;
+        (hours > 0.0).then(|| lost / hours)
+    }
+}
+
+/// Directory of the history logs, $XDG_STATE_HOME/powernotd/history
+pub fn get_history_dir() -> Option<PathBuf> {
+    get_state_dir().map(|dir| dir.join(HISTORY_DIR_NAME))
+}
+
+/// The current log of the battery, the previous one has the suffix '.1'
+fn log_path(dir: &Path, battery: Option<&Battery>) -> PathBuf {
+    dir.join(format!("{}.jsonl", battery.unwrap_or(DEFAULT_BATTERY)))
+}
+
+/// The log of the suspends of the battery, rotated the same way
+fn suspend_log_path(dir: &Path, battery: Option<&Battery>) -> PathBuf {
+    dir.join(format!(
+        "{}-suspend.jsonl",
+        battery.unwrap_or(DEFAULT_BATTERY)
+    ))
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    path.with_extension("jsonl.1")
+}
+
+/// Append the value as one line of json to the log at path. The log is rotated once it exceeds
+/// max_size bytes, replacing the previously rotated log.
+fn append_line<T: Serialize>(path: &Path, value: &T, max_size: u64) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.len() >= max_size) {
+        std::fs::rename(path, rotated_path(path))?;
+    }
+
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+/// All values of the rotated and the current log at path, oldest first. Lines that cannot be
+/// parsed, e.g. one cut off by a crash, are skipped.
+fn read_lines<T: DeserializeOwned>(path: &Path) -> std::io::Result<Vec<T>> {
+    let mut values = vec![];
+    for path in [rotated_path(path), path.to_owned()] {
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for line in BufReader::new(file).lines() {
+            if let Ok(value) = serde_json::from_str::<T>(&line?) {
+                values.push(value);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Append the sample to the log of the battery in dir, rotated once it exceeds max_size bytes
+pub fn append(
+    dir: &Path,
+    battery: Option<&Battery>,
+    sample: &Sample,
+    max_size: u64,
+) -> std::io::Result<()> {
+    append_line(&log_path(dir, battery), sample, max_size)
+}
+
+/// Samples of the battery in dir taken at or after since, oldest first
+pub fn read(dir: &Path, battery: Option<&Battery>, since: u64) -> std::io::Result<Vec<Sample>> {
+    let mut samples = read_lines::<Sample>(&log_path(dir, battery))?;
+    samples.retain(|sample| sample.timestamp >= since);
+    Ok(samples)
+}
+
+/// Append the suspend to the suspend log of the battery in dir, rotated once it exceeds max_size
+/// bytes
+pub fn append_suspend(
+    dir: &Path,
+    battery: Option<&Battery>,
+    entry: &SuspendEntry,
+    max_size: u64,
+) -> std::io::Result<()> {
+    append_line(&suspend_log_path(dir, battery), entry, max_size)
+}
+
+/// Suspends of the battery in dir that ended at or after since, oldest first
+pub fn read_suspends(
+    dir: &Path,
+    battery: Option<&Battery>,
+    since: u64,
+) -> std::io::Result<Vec<SuspendEntry>> {
+    let mut entries = read_lines::<SuspendEntry>(&suspend_log_path(dir, battery))?;
+    entries.retain(|entry| entry.resumed_at >= since);
+    Ok(entries)
+}
+
+/// One line per sample with time, level, status, energy, power draw and AC state
+pub fn format_table(samples: &[Sample]) -> String {
+    let mut table = String::from("Time (UTC)        Level  Status        Energy    Power    AC\n");
+    for sample in samples {
+        let optional = |value: Option<f64>, unit: &str| {
+            value.map_or("-".to_string(), |value| format!("{:.1} {}", value, unit))
+        };
+        let ac = match sample.ac_online {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "-",
+        };
+        table.push_str(&format!(
+            "{}  {:>4}%  {:<12}  {:>8}  {:>7}  {}\n",
+            format_timestamp(sample.timestamp),
+            sample.level,
+            sample.status.as_str(),
+            optional(sample.energy_wh, "Wh"),
+            optional(sample.power_w, "W"),
+            ac
+        ));
+    }
+    table
+}
+
+/// The levels as a sparkline of at most width characters, each character is the average of the
+/// samples it covers
+pub fn format_sparkline(samples: &[Sample], width: usize) -> String {
+    if samples.is_empty() || width == 0 {
+        return String::new();
+    }
+    let chunk_size = samples.len().div_ceil(width);
+    samples
+        .chunks(chunk_size)
+        .map(|chunk| {
+            let level = chunk.iter().map(|sample| sample.level).sum::<u32>() / chunk.len() as u32;
+            let index = (level.min(100) as usize * (SPARKS.len() - 1) + 50) / 100;
+            SPARKS[index]
+        })
+        .collect()
+}
+
+/// Samples as csv with a header line
+pub fn format_csv(samples: &[Sample]) -> String {
+    let mut csv = String::from("timestamp,level,status,energy_wh,power_w,ac_online\n");
+    for sample in samples {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            sample.timestamp,
+            sample.level,
+            sample.status.as_str(),
+            optional(sample.energy_wh.map(|energy| energy.to_string())),
+            optional(sample.power_w.map(|power| power.to_string())),
+            optional(sample.ac_online.map(|online| online.to_string())),
+        ));
+    }
+    csv
+}
+
+/// One line per suspend with start time, duration, levels and drain per hour
+pub fn format_suspend_table(entries: &[SuspendEntry]) -> String {
+    let mut table = String::from("Suspended (UTC)   Duration  Level            Drain\n");
+    for entry in entries {
+        let drain = entry
+            .percent_per_hour()
+            .map_or("-".to_string(), |drain| format!("{:.1}%/h", drain));
+        table.push_str(&format!(
+            "{}  {:>8}  {:>3}% → {:>3}%  {:>7}\n",
+            format_timestamp(entry.suspended_at),
+            format_duration(entry.duration()),
+            entry.start_level,
+            entry.end_level,
+            drain
+        ));
+    }
+    table
+}
+
+/// Suspends as csv with a header line
+pub fn format_suspend_csv(entries: &[SuspendEntry]) -> String {
+    let mut csv = String::from(
+        "suspended_at,resumed_at,start_level,end_level,start_energy_wh,end_energy_wh\n",
+    );
+    for entry in entries {
+        let optional =
+            |value: Option<f64>| value.map(|value| value.to_string()).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            entry.suspended_at,
+            entry.resumed_at,
+            entry.start_level,
+            entry.end_level,
+            optional(entry.start_energy_wh),
+            optional(entry.end_energy_wh),
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn sample(timestamp: u64, level: u32) -> Sample {
+        Sample {
+            timestamp,
+            level,
+            status: ChargingStatus::Discharging,
+            energy_wh: None,
+            power_w: Some(7.5),
+            ac_online: Some(false),
+        }
+    }
+
+    #[test]
+    fn append_rotates_and_read_filters_by_time() {
+        let dir = TestDir::new("history");
+        let line_size = serde_json::to_string(&sample(100, 50)).unwrap().len() as u64 + 1;
+        for timestamp in 100..105 {
+            append(&dir, None, &sample(timestamp, 50), 2 * line_size).unwrap();
+        }
+
+        // the log holding the first two samples was replaced by the next rotation
+        let samples = read(&dir, None, 0).unwrap();
+        let timestamps = samples.iter().map(|sample| sample.timestamp);
+        assert_eq!(timestamps.collect::<Vec<_>>(), vec![102, 103, 104]);
+        assert_eq!(read(&dir, None, 104).unwrap(), vec![sample(104, 50)]);
+    }
+
+    #[test]
+    fn suspends_are_logged_separately() {
+        let dir = TestDir::new("suspends");
+        let entry = SuspendEntry {
+            suspended_at: 0,
+            resumed_at: 4 * 3600,
+            start_level: 80,
+            end_level: 70,
+            start_energy_wh: None,
+            end_energy_wh: None,
+        };
+        append(&dir, None, &sample(100, 50), 1024).unwrap();
+        append_suspend(&dir, None, &entry, 1024).unwrap();
+
+        assert_eq!(read_suspends(&dir, None, 0).unwrap(), vec![entry.clone()]);
+        assert_eq!(read(&dir, None, 0).unwrap(), vec![sample(100, 50)]);
+        assert_eq!(entry.percent_per_hour(), Some(2.5));
+    }
+
+    #[test]
+    fn sparkline_averages_levels() {
+        let samples = [sample(0, 0), sample(1, 100), sample(2, 100), sample(3, 100)];
+        assert_eq!(format_sparkline(&samples, 2), "▅█");
+        assert_eq!(format_sparkline(&samples, 10), "▁███");
+    }
+}
@@ -0,0 +1,287 @@
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::notification::{CommandSpec, Urgency};
+use crate::{send_message, ChargingStatus, Error};
+
+/// Timeout for commands that do not set their own
+pub const DEFAULT_TIMEOUT_SECS: u32 = 30;
+
+/// Interval in which a running command is checked for completion
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HooksConfig {
+    // seconds after which a command is killed, can be overridden per command
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u32,
+    // send a desktop notification when a command fails, otherwise failures are only logged
+    #[serde(default)]
+    pub notify_on_failure: bool,
+}
+
+fn default_timeout_secs() -> u32 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        HooksConfig {
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+            notify_on_failure: false,
+        }
+    }
+}
+
+/// The event a command is run for. It is exported as POWERNOTD_* environment variables and
+/// written as json to the command's stdin.
+#[derive(Serialize, Debug, Clone)]
+pub struct HookEvent {
+    // kind of the event such as 'threshold' or 'full'
+    pub event: String,
+    pub level: u32,
+    pub status: ChargingStatus,
+    pub battery: String,
+    // threshold level that was reached, only set for threshold events
+    pub threshold: Option<u32>,
+}
+
+impl HookEvent {
+    fn env_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("POWERNOTD_EVENT", self.event.clone()),
+            ("POWERNOTD_LEVEL", self.level.to_string()),
+            ("POWERNOTD_STATUS", self.status.to_string()),
+            ("POWERNOTD_BATTERY", self.battery.clone()),
+        ];
+        if let Some(threshold) = self.threshold {
+            vars.push(("POWERNOTD_THRESHOLD", threshold.to_string()));
+        }
+        vars
+    }
+}
+
+/// Run the commands one after another on a background thread so the daemon is not blocked
+pub fn spawn(commands: &[CommandSpec], event: &HookEvent, config: &HooksConfig) {
+    spawn_in_order(vec![(commands.to_vec(), event.clone())], config);
+}
+
+/// Run the command lists one after another on a single background thread, each with its own event.
+/// Used for thresholds crossed at once so their commands run in the order of the thresholds.
+pub fn spawn_in_order(mut lists: Vec<(Vec<CommandSpec>, HookEvent)>, config: &HooksConfig) {
+    lists.retain(|(commands, _)| !commands.is_empty());
+    if lists.is_empty() {
+        return;
+    }
+    let default_timeout = config.timeout_secs;
+    let notify_on_failure = config.notify_on_failure;
+    thread::spawn(move || run_in_order(&lists, default_timeout, notify_on_failure));
+}
+
+/// Run the command lists in order, a failure only skips the remaining commands of its own list
+fn run_in_order(
+    lists: &[(Vec<CommandSpec>, HookEvent)],
+    default_timeout_secs: u32,
+    notify_on_failure: bool,
+) {
+    for (commands, event) in lists {
+        run_all(commands, event, default_timeout_secs, notify_on_failure);
+    }
+}
+
+/// Run the commands in order honoring their delay. The exit status of each command is logged and
+/// the remaining commands are skipped after a failure unless the command continues on error.
+fn run_all(
+    commands: &[CommandSpec],
+    event: &HookEvent,
+    default_timeout_secs: u32,
+    notify_on_failure: bool,
+) {
+    for (index, command) in commands.iter().enumerate() {
+        if let Some(delay) = command.delay_secs() {
+            thread::sleep(Duration::from_secs(delay.into()));
+        }
+        match run(command, event, default_timeout_secs) {
+            Ok(()) => eprintln!("Command '{}' exited successfully", command.run()),
+            Err(err) => {
+                eprintln!("{}", err);
+                if notify_on_failure {
+                    report_failure(&err);
+                }
+                if !command.continue_on_error() {
+                    let skipped = commands.len() - index - 1;
+                    if skipped > 0 {
+                        eprintln!("Skipping the {} remaining command(s)", skipped);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn report_failure(err: &Error) {
+    let result = send_message(
+        "Powernotd command failed",
+        &err.to_string(),
+        &Urgency::Normal,
+        None,
+    );
+    if let Err(err) = result {
+        eprintln!("{}", err);
+    }
+}
+
+/// Run the command and wait for it, it is killed if it does not finish within its timeout
+pub fn run(
+    command: &CommandSpec,
+    event: &HookEvent,
+    default_timeout_secs: u32,
+) -> Result<(), Error> {
+    let command_line = command.run();
+    let command_error = |reason: String| Error::Command {
+        command: command_line.to_owned(),
+        reason,
+    };
+    let args = shell_words::split(command_line).map_err(|err| command_error(err.to_string()))?;
+    let [program, rest @ ..] = args.as_slice() else {
+        return Err(command_error("missing command for running".to_string()));
+    };
+
+    let mut process = Command::new(program);
+    process
+        .args(rest)
+        .envs(event.env_vars())
+        .envs(command.env())
+        .stdin(Stdio::piped())
+        // own process group, so a timeout also kills the processes the command started
+        .process_group(0);
+    if let Some(cwd) = command.cwd() {
+        process.current_dir(cwd);
+    }
+    let mut child = process
+        .spawn()
+        .map_err(|err| command_error(err.to_string()))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // commands are free to ignore stdin, so a closed pipe is not an error
+        let _ = serde_json::to_writer(&mut stdin, event);
+        let _ = stdin.write_all(b"\n");
+    }
+
+    let timeout = Duration::from_secs(
+        command
+            .timeout_secs()
+            .unwrap_or(default_timeout_secs)
+            .into(),
+    );
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(command_error(format!("exited with {}", status))),
+            Ok(None) if start.elapsed() >= timeout => {
+                // the child leads its process group, so its pid is the group id
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                let _ = child.wait();
+                return Err(command_error(format!(
+                    "killed after timeout of {}s",
+                    timeout.as_secs()
+                )));
+            }
+            Ok(None) => thread::sleep(WAIT_INTERVAL),
+            Err(err) => return Err(command_error(err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn event() -> HookEvent {
+        HookEvent {
+            event: "threshold".to_string(),
+            level: 12,
+            status: ChargingStatus::Discharging,
+            battery: "BAT0".to_string(),
+            threshold: Some(15),
+        }
+    }
+
+    #[test]
+    fn command_receives_env_and_stdin() {
+        let command = CommandSpec::Line(
+            r#"sh -c 'read -r json && test "$POWERNOTD_THRESHOLD" = 15 && case "$json" in *"\"level\":12"*) exit 0;; *) exit 1;; esac'"#
+                .to_string(),
+        );
+        run(&command, &event(), DEFAULT_TIMEOUT_SECS).unwrap();
+    }
+
+    #[test]
+    fn command_is_killed_after_timeout() {
+        let command = CommandSpec::Detailed {
+            run: "sleep 10".to_string(),
+            cwd: None,
+            env: Default::default(),
+            timeout_secs: Some(1),
+            delay_secs: None,
+            continue_on_error: false,
+        };
+        assert!(run(&command, &event(), DEFAULT_TIMEOUT_SECS).is_err());
+    }
+
+    #[test]
+    fn background_processes_are_killed_after_timeout() {
+        let dir = TestDir::new("group");
+        let pid_path = dir.join("pid");
+        let command = CommandSpec::Detailed {
+            run: format!("sh -c 'sleep 30 & echo $! > {}; wait'", pid_path.display()),
+            cwd: None,
+            env: Default::default(),
+            timeout_secs: Some(1),
+            delay_secs: None,
+            continue_on_error: false,
+        };
+        assert!(run(&command, &event(), DEFAULT_TIMEOUT_SECS).is_err());
+
+        let pid = std::fs::read_to_string(&pid_path).unwrap();
+        // the killed sleep is gone or a zombie waiting for its new parent to reap it
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(stat.map_or(true, |stat| stat.contains(") Z ")));
+    }
+
+    #[test]
+    fn command_lists_run_in_order() {
+        let dir = TestDir::new("order");
+        let path = dir.join("order");
+        let append = |delay: &str| {
+            CommandSpec::Line(format!(
+                r#"sh -c 'sleep {} && echo "$POWERNOTD_THRESHOLD" >> {}'"#,
+                delay,
+                path.to_string_lossy()
+            ))
+        };
+        let threshold = |threshold| HookEvent {
+            threshold: Some(threshold),
+            ..event()
+        };
+        // the first list is the slowest, it still finishes before the next one starts
+        let lists = vec![
+            (vec![append("0.3")], threshold(30)),
+            (vec![append("0.1")], threshold(20)),
+            (vec![append("0")], threshold(10)),
+        ];
+
+        run_in_order(&lists, DEFAULT_TIMEOUT_SECS, false);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "30\n20\n10\n");
+    }
+}
@@ -1,71 +1,137 @@
+pub mod battery;
+pub mod charge_limit;
+pub mod charger;
 pub mod config;
+pub mod control;
+pub mod daemon;
+pub mod dbus;
+pub mod drain;
+pub mod duration;
+mod error;
+pub mod history;
+pub mod hooks;
+pub mod logind;
 pub mod notification;
+pub mod power_action;
+pub mod session;
+pub mod state;
+pub mod temperature;
+pub mod template;
+#[cfg(test)]
+mod test_dir;
+pub mod watch;
 
+pub use error::Error;
 use notification::{BatteryFullNotification, Urgency};
-use std::fs::File;
-use std::io::prelude::*;
-use std::{collections::HashMap, process::Command};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+use std::{collections::HashMap, thread};
 
 pub type Battery = str;
 
 pub const DEFAULT_BATTERY: &Battery = "BAT0";
 
 pub fn get_charging_status_path(battery: Option<&Battery>) -> String {
-    let battery = battery.as_deref().unwrap_or(DEFAULT_BATTERY);
+    let battery = battery.unwrap_or(DEFAULT_BATTERY);
     format!("/sys/class/power_supply/{}/status", battery)
 }
 
 pub fn get_power_status_path(battery: Option<&Battery>) -> String {
-    let battery = battery.as_deref().unwrap_or(DEFAULT_BATTERY);
+    let battery = battery.unwrap_or(DEFAULT_BATTERY);
     format!("/sys/class/power_supply/{}/capacity", battery)
 }
 
-/// Return the current battery level
-pub fn get_current_power(battery: Option<&Battery>) -> u32 {
-    let power_status_path = get_power_status_path(battery);
-    let mut file = File::open(power_status_path).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    contents.trim().parse().expect("failed to parse number")
+/// Read a sysfs attribute and return its trimmed contents
+pub fn read_attribute(path: &Path) -> Result<String, Error> {
+    std::fs::read_to_string(path)
+        .map(|contents| contents.trim().to_string())
+        .map_err(|source| Error::ReadAttribute {
+            path: path.to_owned(),
+            source,
+        })
 }
 
-#[derive(Debug)]
+/// Return the current battery level, see battery::read_level for batteries without a capacity
+pub fn get_current_power(battery: Option<&Battery>) -> Result<u32, Error> {
+    battery::read_level(&battery::get_battery_dir(battery))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ChargingStatus {
     Charging,
     Discharging,
     Full,
+    // plugged in but held below full, e.g. by a charge limit
+    NotCharging,
     Unknown,
 }
 
 impl ChargingStatus {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match *self {
             ChargingStatus::Charging => "charging",
             ChargingStatus::Discharging => "discharging",
             ChargingStatus::Full => "full",
+            ChargingStatus::NotCharging => "not_charging",
             ChargingStatus::Unknown => "unknown",
         }
     }
-    fn as_string(&self) -> String {
-        self.as_str().to_owned()
+
+    /// True if AC is connected, the battery does not have to be charging
+    pub fn is_plugged_in(&self) -> bool {
+        matches!(
+            self,
+            ChargingStatus::Charging | ChargingStatus::Full | ChargingStatus::NotCharging
+        )
+    }
+}
+
+impl std::fmt::Display for ChargingStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-pub fn get_status_charging(battery: Option<&Battery>) -> String {
-    let status_charging_path = get_charging_status_path(battery);
-    let mut file = File::open(status_charging_path).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    match contents.trim() {
-        "Charging" => ChargingStatus::Charging.as_string(),
-        "Discharging" => ChargingStatus::Discharging.as_string(),
-        "Full" | "Not charging" => ChargingStatus::Full.as_string(),
-        _ => ChargingStatus::Unknown.as_string(),
+pub fn get_status_charging(battery: Option<&Battery>) -> Result<ChargingStatus, Error> {
+    battery::read_status(&battery::get_battery_dir(battery))
+}
+
+/// Call f until it succeeds or the attempts are used up, the delay between attempts doubles each
+/// time. Returns the last error if all attempts fail.
+pub fn retry<T>(
+    attempts: u32,
+    initial_delay: Duration,
+    mut f: impl FnMut() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut delay = initial_delay;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt >= attempts => return Err(err),
+            Err(err) => {
+                eprintln!("Attempt {} of {} failed: {}", attempt, attempts, err);
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+        }
     }
 }
 
+/// Attempts for delivering a notification, the notification server might just be restarting
+const SEND_ATTEMPTS: u32 = 3;
+const SEND_RETRY_DELAY: Duration = Duration::from_secs(1);
+
 /// send a message using linux notify-send api
-pub fn send_message(title: &str, message: &str, urgency: &Urgency, time_secs: Option<u32>) {
+pub fn send_message(
+    title: &str,
+    message: &str,
+    urgency: &Urgency,
+    time_secs: Option<u32>,
+) -> Result<(), Error> {
     let mut notification = notify_rust::Notification::new();
 
     notification
@@ -77,61 +143,44 @@ pub fn send_message(title: &str, message: &str, urgency: &Urgency, time_secs: Op
         notification.timeout(notify_rust::Timeout::Milliseconds(wait_time * 1000));
         //milliseconds
     }
-    notification.show().unwrap();
+    retry(SEND_ATTEMPTS, SEND_RETRY_DELAY, || {
+        notification.show()?;
+        Ok(())
+    })
 }
 
-pub fn run_command(command: &str) {
-    let args_res = shell_words::split(command);
-    if args_res.is_err() {
-        eprintln!(
-            "Could not run command: {}, err: {:?}",
-            command.to_owned(),
-            args_res
-        );
-        return;
-    }
-    let actual_args = args_res.unwrap();
-    match actual_args.as_slice() {
-        [first, rest @ ..] => {
-            let output = Command::new(first)
-                .args(rest)
-                .output()
-                .unwrap_or_else(|_| panic!("Failed to run command {}", command));
-            if !output.status.success() {
-                eprintln!("status: {}", output.status);
-                eprintln!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-                eprintln!("stdout: {}", String::from_utf8_lossy(&output.stdout));
-            }
-        }
-        [] => {
-            eprintln!("Missing command for running");
+/// Send a message like send_message but on its own thread, so the retries do not block the
+/// caller. Failures after the last attempt are only logged.
+pub fn send_message_in_background(
+    title: &str,
+    message: &str,
+    urgency: &Urgency,
+    time_secs: Option<u32>,
+) -> Result<(), Error> {
+    let (title, message, urgency) = (title.to_owned(), message.to_owned(), *urgency);
+    thread::spawn(move || {
+        if let Err(err) = send_message(&title, &message, &urgency, time_secs) {
+            eprintln!("Failed to send notification '{}': {}", title, err);
         }
-    }
+    });
+    Ok(())
 }
 
-/// Send a notification using the rust_notify library. The title and message are used from the
-/// Notification if given and templated by replacing '{}' with the current percentage. In addition,
-/// a system command is run if specified in the Notification.
-pub fn send_notification(level: &u32, notification: &notification::Notification) {
-    let title = notification
-        .title
-        .clone()
-        .unwrap_or("Battery Status".to_string());
-    let message = notification.message.clone().unwrap_or("{}".to_string());
-    let percent = format!("{}", level);
-
-    send_message(
-        &title.replace("{}", &percent),
-        &message.replace("{}", &percent),
-        &notification.urgency,
-        notification.time_secs,
-    );
-    if notification.command.is_some() {
-        run_command(notification.command.as_ref().unwrap());
-    }
+/// Title and message of the Notification with the placeholders replaced by the values of the
+/// snapshot
+pub fn render_notification(
+    notification: &notification::Notification,
+    snapshot: &battery::Snapshot,
+) -> (String, String) {
+    let title = notification.title.as_deref().unwrap_or("Battery Status");
+    let message = notification.message.as_deref().unwrap_or("{}");
+    (
+        template::render(title, snapshot),
+        template::render(message, snapshot),
+    )
 }
 
-pub fn notify_now(level: &u32) {
+pub fn notify_now(level: &u32) -> Result<(), Error> {
     let percent = format!("{}%", level);
     let default_wait_time = 10; // seconds
     send_message(
@@ -139,17 +188,43 @@ pub fn notify_now(level: &u32) {
         &percent,
         &Urgency::Normal,
         Some(default_wait_time),
-    );
+    )
 }
 
-/// Find lowest threshold which has been passed with the current battery level
-pub fn find_lowest_threshold(
+/// Find lowest threshold which has been passed with the current battery level, the map holds
+/// whatever belongs to each threshold level
+pub fn find_lowest_threshold<T>(current: u32, notified: &HashMap<u32, T>) -> Option<u32> {
+    notified.keys().copied().filter(|&key| key >= current).min()
+}
+
+/// Thresholds that have to be notified for the current level in the order they were crossed, so
+/// the most severe one is last. Besides the lowest passed threshold this includes all thresholds
+/// in between that were skipped since the last check, e.g. when dropping from 31% to 9% after a
+/// suspend. Without a last level, e.g. on the first check after a cold start, no threshold counts
+/// as skipped. Empty if the lowest passed threshold was already notified.
+pub fn find_crossed_thresholds(
     current: u32,
+    last: Option<u32>,
     notified: &HashMap<u32, notification::Notification>,
-) -> Option<u32> {
-    let keys = notified.keys().cloned().collect::<Vec<_>>();
+) -> Vec<u32> {
+    let Some(lowest) = find_lowest_threshold(current, notified) else {
+        return vec![];
+    };
+    let due = notified
+        .get(&lowest)
+        .is_some_and(|notification| !notification.notified);
+    if !due || last.is_some_and(|last| current >= last) {
+        return vec![];
+    }
 
-    keys.into_iter().filter(|&key| key >= current).min()
+    let mut crossed = notified
+        .keys()
+        .cloned()
+        .filter(|&key| last.is_some_and(|last| key > lowest && key < last))
+        .collect::<Vec<_>>();
+    crossed.sort_by(|a, b| b.cmp(a));
+    crossed.push(lowest);
+    crossed
 }
 
 /// Reset all notifications which are not the current threshold_val
@@ -164,37 +239,105 @@ pub fn reset_other_notifications(
     }
 }
 
-/// notify if battery is fully charged
+/// Message template of the full notification if it is due because the battery is fully charged,
+/// or charged up to the given charge limit or the battery reports that it is held below full. The
+/// notification is marked as notified, it is due again once the battery discharged.
 pub fn check_notify_full_battery(
-    current: &u32,
-    last: &u32,
+    current: u32,
+    last: u32,
     full_notification: &mut BatteryFullNotification,
-) {
+    status: ChargingStatus,
+    charge_limit: Option<u32>,
+) -> Option<String> {
     // if already notified then do nothing
     if full_notification.notified || !full_notification.enabled {
-        return;
+        return None;
     }
 
     // if charge is decreasing do not notify again
-    if *last >= *current {
+    if last >= current {
         // if battery status is decreasing then we want to notify again if reaching full capacity
         full_notification.notified = false;
-        return;
+        return None;
     }
 
-    let title = full_notification
-        .title
-        .clone()
-        .unwrap_or("Battery Status".to_string());
-    let message = full_notification
-        .message
-        .clone()
-        .unwrap_or("Fully Charged 100%".to_string());
-    if *current >= 100 {
-        send_message(&title, &message, &full_notification.urgency, None);
-        if full_notification.command.is_some() {
-            run_command(full_notification.command.as_ref().unwrap());
-        }
-        full_notification.notified = true;
+    let at_limit = current < 100
+        && (charge_limit.is_some_and(|limit| current >= limit)
+            || status == ChargingStatus::NotCharging);
+    if current < 100 && !at_limit {
+        return None;
+    }
+    // only try once, a failed notification is not repeated on every tick
+    full_notification.notified = true;
+    let message = if at_limit {
+        full_notification
+            .limit_message
+            .clone()
+            .unwrap_or(notification::DEFAULT_LIMIT_MESSAGE.to_string())
+    } else {
+        full_notification
+            .message
+            .clone()
+            .unwrap_or("Fully Charged 100%".to_string())
+    };
+    Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notification::Notification;
+
+    fn thresholds(levels: &[u32]) -> HashMap<u32, Notification> {
+        levels
+            .iter()
+            .map(|&level| {
+                let notification = Notification {
+                    level,
+                    ..Default::default()
+                };
+                (level, notification)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crossed_thresholds_include_skipped_ones() {
+        let notified = thresholds(&[30, 20, 15, 10, 5]);
+        assert_eq!(
+            find_crossed_thresholds(9, Some(31), &notified),
+            vec![30, 20, 15, 10]
+        );
+        assert_eq!(find_crossed_thresholds(19, Some(21), &notified), vec![20]);
+        // the first check after a cold start only notifies the current threshold
+        assert_eq!(find_crossed_thresholds(9, None, &notified), vec![10]);
+    }
+
+    #[test]
+    fn notification_applies_to_listed_statuses() {
+        let notification: Notification =
+            serde_json::from_str(r#"{"level": 20, "urgency": "Low", "statuses": ["discharging"]}"#)
+                .unwrap();
+        assert!(notification.applies_to(ChargingStatus::Discharging));
+        assert!(!notification.applies_to(ChargingStatus::NotCharging));
+    }
+
+    #[test]
+    fn threshold_level_can_be_a_capacity_level() {
+        let notification: Notification =
+            serde_json::from_str(r#"{"level": "Critical", "urgency": "Critical"}"#).unwrap();
+        assert_eq!(notification.level, 5);
+        assert!(
+            serde_json::from_str::<Notification>(r#"{"level": "Empty", "urgency": "Low"}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn notified_threshold_is_not_crossed_again() {
+        let mut notified = thresholds(&[30, 20]);
+        notified.get_mut(&20).unwrap().notified = true;
+        assert!(find_crossed_thresholds(18, Some(19), &notified).is_empty());
+        assert!(find_crossed_thresholds(25, Some(25), &notified).is_empty());
     }
 }
@@ -0,0 +1,149 @@
+use std::os::fd::OwnedFd;
+
+use zbus::blocking::Connection;
+
+use crate::notification::PowerAction;
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1",
+    gen_async = false
+)]
+pub trait Manager {
+    fn suspend(&self, interactive: bool) -> zbus::Result<()>;
+
+    fn hibernate(&self, interactive: bool) -> zbus::Result<()>;
+
+    fn hybrid_sleep(&self, interactive: bool) -> zbus::Result<()>;
+
+    fn power_off(&self, interactive: bool) -> zbus::Result<()>;
+
+    /// Take an inhibitor lock that is held until the returned file descriptor is closed
+    fn inhibit(
+        &self,
+        what: &str,
+        who: &str,
+        why: &str,
+        mode: &str,
+    ) -> zbus::Result<zbus::zvariant::OwnedFd>;
+
+    /// Emitted with start set to true before the system suspends and false after it resumed
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+/// Ask logind on the given bus, usually the system bus, to execute the power action. The request
+/// is not interactive so it fails instead of waiting for an authentication prompt.
+pub fn execute(connection: &Connection, action: PowerAction) -> zbus::Result<()> {
+    let manager = ManagerProxy::new(connection)?;
+    match action {
+        PowerAction::Hibernate => manager.hibernate(false),
+        PowerAction::Suspend => manager.suspend(false),
+        PowerAction::Poweroff => manager.power_off(false),
+        PowerAction::HybridSleep => manager.hybrid_sleep(false),
+    }
+}
+
+/// Call on_sleep with the argument of every PrepareForSleep signal emitted by logind on the system
+/// bus. Blocks until the connection is closed.
+///
+/// A delay lock on sleep is held while waiting so the system does not suspend before the battery
+/// was recorded. It is passed to on_sleep when the system is about to suspend, the suspend
+/// continues once it is dropped. After resuming the lock is taken again.
+pub fn watch_sleep(mut on_sleep: impl FnMut(bool, Option<OwnedFd>)) -> zbus::Result<()> {
+    let connection = Connection::system()?;
+    let manager = ManagerProxy::new(&connection)?;
+    let signals = manager.receive_prepare_for_sleep()?;
+    let mut lock = inhibit_sleep(&manager);
+    for signal in signals {
+        let start = signal.args()?.start;
+        if start {
+            on_sleep(true, lock.take());
+        } else {
+            lock = lock.or_else(|| inhibit_sleep(&manager));
+            on_sleep(false, None);
+        }
+    }
+    Ok(())
+}
+
+/// Delay lock on sleep, None if logind refused it
+fn inhibit_sleep(manager: &ManagerProxy) -> Option<OwnedFd> {
+    match manager.inhibit("sleep", "powernotd", "record battery level", "delay") {
+        Ok(fd) => Some(fd.into()),
+        Err(err) => {
+            eprintln!("Could not delay suspend, error: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+
+    struct MockManager {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.login1.Manager")]
+    impl MockManager {
+        fn suspend(&self, interactive: bool) {
+            self.record("suspend", interactive);
+        }
+
+        fn hibernate(&self, interactive: bool) {
+            self.record("hibernate", interactive);
+        }
+
+        fn hybrid_sleep(&self, interactive: bool) {
+            self.record("hybrid-sleep", interactive);
+        }
+
+        fn power_off(&self, interactive: bool) {
+            self.record("poweroff", interactive);
+        }
+    }
+
+    impl MockManager {
+        fn record(&self, method: &str, interactive: bool) {
+            let call = format!("{} {}", method, interactive);
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    #[test]
+    fn execute_calls_logind_manager() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let (server_stream, client_stream) = UnixStream::pair().unwrap();
+        let mock = MockManager {
+            calls: calls.clone(),
+        };
+        let server = std::thread::spawn(move || {
+            zbus::blocking::connection::Builder::async_io_unix_stream(server_stream)
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at("/org/freedesktop/login1", mock)
+                .unwrap()
+                .build()
+                .unwrap()
+        });
+        let client = zbus::blocking::connection::Builder::async_io_unix_stream(client_stream)
+            .p2p()
+            .build()
+            .unwrap();
+        let _server = server.join().unwrap();
+
+        execute(&client, PowerAction::Hibernate).unwrap();
+        execute(&client, PowerAction::HybridSleep).unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["hibernate false", "hybrid-sleep false"]
+        );
+    }
+}
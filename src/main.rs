@@ -1,23 +1,28 @@
 mod cli;
 
-use crate::cli::Args;
+use crate::cli::{Args, ChargeLimitCommands, Commands, ConfigCommands, ExportFormat};
 use clap::Parser;
 use powernotd::config;
+use powernotd::control;
+use powernotd::daemon::{Daemon, DaemonStatus};
 use powernotd::*;
-use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
-use std::{thread, time};
-
-use powernotd::notification::Notification;
 
 fn main() {
     let args = Args::parse();
 
+    if let Some(command) = &args.command {
+        run_subcommand(command, &args);
+        return;
+    }
+
     let battery: Option<&Battery> = args.battery.as_deref();
 
-    // these paths are required for reading power supply status
+    // these paths are required for reading power supply status, the level is read from whichever
+    // of capacity, energy, charge or capacity_level the battery provides
     let required_paths = vec![
-        PathBuf::from(get_power_status_path(battery)),
+        battery::get_battery_dir(battery),
         PathBuf::from(get_charging_status_path(battery)),
     ];
     for path in required_paths {
@@ -29,30 +34,59 @@ fn main() {
             std::process::exit(1);
         }
     }
+    // one-shot queries fail early if none of the attributes the level is read from is usable, the
+    // daemon starts anyway and retries with its backoff
+    let one_shot = args.status_level
+        || args.charging_state
+        || args.notify_now
+        || args.json
+        || args.format.is_some();
+    if one_shot {
+        exit_on_error(battery::read_level(&battery::get_battery_dir(battery)));
+    }
+
+    // a running daemon for the same battery answers instead of reading sysfs again
+    let daemon_status = if args.status_level || args.charging_state || args.notify_now {
+        query_daemon_status(battery)
+    } else {
+        None
+    };
 
     if args.status_level {
-        let current = get_current_power(battery);
+        let current = match &daemon_status {
+            Some(status) => status.level,
+            None => exit_on_error(get_current_power(battery)),
+        };
         println!("{}%", current);
         return;
     }
 
     if args.charging_state {
-        let status = get_status_charging(battery);
+        let status = match daemon_status {
+            Some(status) => status.status,
+            None => exit_on_error(get_status_charging(battery)),
+        };
         println!("{}", status);
         return;
     }
 
     if args.notify_now {
-        let current = get_current_power(battery);
-        notify_now(&current);
+        let current = match &daemon_status {
+            Some(status) => status.level,
+            None => exit_on_error(get_current_power(battery)),
+        };
+        exit_on_error(notify_now(&current));
         return;
     }
 
-    let mut config = match args.config_file {
-        Some(string) => {
-            let path = PathBuf::from(string);
-            config::get_specific_config(path)
-        }
+    if args.json || args.format.is_some() {
+        print_snapshot(&args, battery);
+        return;
+    }
+
+    let config_file = args.config_file.as_ref().map(PathBuf::from);
+    let config = match &config_file {
+        Some(path) => config::get_specific_config(path.clone()),
         None => config::get_or_create_config(),
     };
 
@@ -79,32 +113,347 @@ fn main() {
         return;
     }
 
-    let sleep_time = time::Duration::from_secs(60);
-    let mut last_battery_level: u32 = 100;
+    let mut daemon = Daemon::new(config, battery, config_file);
+    if daemon.restore_state() {
+        println!("Restored threshold state of the previous run");
+    } else {
+        daemon.startup();
+    }
+    daemon.run();
+}
+
+/// Print the error and exit, used for the one-shot cli commands
+fn exit_on_error<T>(result: Result<T, Error>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
+}
+
+fn run_subcommand(command: &Commands, args: &Args) {
+    match command {
+        Commands::Config { action } => match action {
+            ConfigCommands::Show { effective } => show_config(args, *effective),
+            ConfigCommands::Migrate { no_backup } => migrate_config(args, !no_backup),
+        },
+        Commands::Control { request } => send_control_request(&request.join(" ")),
+        Commands::Watch { format } => watch_status(args, *format),
+        Commands::Health { json } => print_health(args.battery.as_deref(), *json),
+        Commands::History {
+            since,
+            sparkline,
+            suspends,
+            export,
+        } => {
+            let battery = args.battery.as_deref();
+            if *suspends {
+                print_suspends(battery, since, *export)
+            } else {
+                print_history(battery, since, *sparkline, *export)
+            }
+        }
+        Commands::ChargeLimit { action } => match action {
+            ChargeLimitCommands::Get => {
+                let limit = exit_on_error(charge_limit::get(args.battery.as_deref()));
+                println!("{}", limit);
+            }
+            ChargeLimitCommands::Set { levels } => {
+                let limit = match levels.as_slice() {
+                    [start, end] => charge_limit::ChargeLimit {
+                        start: Some(*start),
+                        end: *end,
+                    },
+                    _ => charge_limit::ChargeLimit {
+                        start: None,
+                        end: levels[0],
+                    },
+                };
+                exit_on_error(charge_limit::set(args.battery.as_deref(), &limit));
+                println!("Charge limit set to {}", limit);
+            }
+        },
+    }
+}
+
+fn watch_status(args: &Args, format: watch::Format) {
+    let config =
+        config::load_effective_config(Some(&user_config_path(args))).unwrap_or_else(|err| {
+            eprintln!("Could not load config, error: {:?}", err);
+            std::process::exit(1);
+        });
+    let thresholds = config
+        .notifications
+        .iter()
+        .map(|notification| (notification.level, notification.urgency))
+        .collect();
+    watch::run(format, args.battery.as_deref(), &thresholds);
+}
+
+/// Print everything known about the battery as json or in the format passed with --format
+fn print_snapshot(args: &Args, battery: Option<&Battery>) {
+    // the active threshold is only shown if the config can be loaded
+    let thresholds = config::load_effective_config(Some(&user_config_path(args)))
+        .map(|config| config.notifications)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|notification| (notification.level, notification))
+        .collect();
+    let snapshot = exit_on_error(battery::get_snapshot(battery, &thresholds));
+    match &args.format {
+        Some(format) => println!("{}", template::render(format, &snapshot)),
+        None => match serde_json::to_string_pretty(&snapshot) {
+            Ok(text) => println!("{}", text),
+            Err(err) => {
+                eprintln!("Could not serialize battery status, error: {}", err);
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+fn print_health(battery: Option<&Battery>, json: bool) {
+    let health = exit_on_error(battery::get_health(battery));
+    if json {
+        match serde_json::to_string_pretty(&health) {
+            Ok(text) => println!("{}", text),
+            Err(err) => {
+                eprintln!("Could not serialize battery health, error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let unknown = || "unknown".to_string();
+    let capacity = match (health.energy_full_wh, health.energy_full_design_wh) {
+        (Some(full), Some(design)) => format!("{:.1} Wh of {:.1} Wh design", full, design),
+        (Some(full), None) => format!("{:.1} Wh", full),
+        _ => unknown(),
+    };
+    println!("Battery:       {}", health.battery);
+    println!(
+        "Manufacturer:  {}",
+        health.manufacturer.unwrap_or_else(unknown)
+    );
+    println!(
+        "Model:         {}",
+        health.model_name.unwrap_or_else(unknown)
+    );
+    println!(
+        "Technology:    {}",
+        health.technology.unwrap_or_else(unknown)
+    );
+    println!(
+        "Serial number: {}",
+        health.serial_number.unwrap_or_else(unknown)
+    );
+    println!("Capacity:      {}", capacity);
+    println!(
+        "Wear:          {}",
+        health
+            .wear_percent
+            .map_or_else(unknown, |wear| format!("{}%", wear))
+    );
+    println!(
+        "Cycles:        {}",
+        health
+            .cycle_count
+            .map_or_else(unknown, |cycles| cycles.to_string())
+    );
+}
+
+/// Width of the sparkline printed by 'history --sparkline'
+const SPARKLINE_WIDTH: usize = 60;
+
+/// The history directory and the unix timestamp the given time span before now, exits if either
+/// cannot be determined
+fn history_dir_since(since: &str) -> (PathBuf, u64) {
+    let Some(span) = duration::parse_duration(since) else {
+        eprintln!(
+            "Invalid time span '{}', use e.g. '30m', '24h' or '7d'",
+            since
+        );
+        std::process::exit(1);
+    };
+    let Some(dir) = history::get_history_dir() else {
+        eprintln!("Could not determine the history directory");
+        std::process::exit(1);
+    };
+    (dir, state::now_secs().saturating_sub(span.as_secs()))
+}
+
+fn print_history(
+    battery: Option<&Battery>,
+    since: &str,
+    sparkline: bool,
+    export: Option<ExportFormat>,
+) {
+    let (dir, since) = history_dir_since(since);
+    let samples = history::read(&dir, battery, since).unwrap_or_else(|err| {
+        eprintln!("Could not read battery history, error: {}", err);
+        std::process::exit(1);
+    });
+
+    match export {
+        Some(ExportFormat::Csv) => print!("{}", history::format_csv(&samples)),
+        Some(ExportFormat::Json) => match serde_json::to_string_pretty(&samples) {
+            Ok(text) => println!("{}", text),
+            Err(err) => {
+                eprintln!("Could not serialize battery history, error: {}", err);
+                std::process::exit(1);
+            }
+        },
+        None if samples.is_empty() => println!(
+            "No samples recorded since {}",
+            duration::format_timestamp(since)
+        ),
+        None if sparkline => {
+            let (first, last) = (&samples[0], &samples[samples.len() - 1]);
+            println!(
+                "{} {}% {} {}% {} (UTC)",
+                duration::format_timestamp(first.timestamp),
+                first.level,
+                history::format_sparkline(&samples, SPARKLINE_WIDTH),
+                last.level,
+                duration::format_timestamp(last.timestamp)
+            );
+        }
+        None => print!("{}", history::format_table(&samples)),
+    }
+}
+
+fn print_suspends(battery: Option<&Battery>, since: &str, export: Option<ExportFormat>) {
+    let (dir, since) = history_dir_since(since);
+    let entries = history::read_suspends(&dir, battery, since).unwrap_or_else(|err| {
+        eprintln!("Could not read suspend history, error: {}", err);
+        std::process::exit(1);
+    });
+
+    match export {
+        Some(ExportFormat::Csv) => print!("{}", history::format_suspend_csv(&entries)),
+        Some(ExportFormat::Json) => match serde_json::to_string_pretty(&entries) {
+            Ok(text) => println!("{}", text),
+            Err(err) => {
+                eprintln!("Could not serialize suspend history, error: {}", err);
+                std::process::exit(1);
+            }
+        },
+        None if entries.is_empty() => println!(
+            "No suspends recorded since {}",
+            duration::format_timestamp(since)
+        ),
+        None => print!("{}", history::format_suspend_table(&entries)),
+    }
+}
 
-    let mut notified: HashMap<u32, Notification> = HashMap::new();
+/// Status of the running daemon if it watches the given battery, None if no daemon is running
+fn query_daemon_status(battery: Option<&Battery>) -> Option<DaemonStatus> {
+    let status = control::request("status").ok()?.status?;
+    (status.battery == battery.unwrap_or(DEFAULT_BATTERY)).then_some(status)
+}
 
-    for notification in config.notifications {
-        notified.insert(notification.level, notification);
+/// Send the request to the running daemon and print its responses, a subscription keeps printing
+/// until the daemon exits
+fn send_control_request(request: &str) {
+    let result = control::connect().and_then(|mut stream| {
+        stream.write_all(format!("{}\n", request).as_bytes())?;
+        let mut failed = false;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            println!("{}", line);
+            let response: control::Response =
+                serde_json::from_str(&line).map_err(std::io::Error::other)?;
+            failed |= !response.ok;
+            if request.trim() != "subscribe" {
+                break;
+            }
+        }
+        Ok(failed)
+    });
+    match result {
+        Ok(false) => {}
+        Ok(true) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("Could not reach the running daemon: {}", err);
+            std::process::exit(1);
+        }
     }
+}
+
+/// Config-file passed with -f or the default one within the user's config directory
+fn user_config_path(args: &Args) -> PathBuf {
+    let path = args
+        .config_file
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(config::get_default_config_path);
+    path.unwrap_or_else(|| {
+        eprintln!("Could not determine the config-file path");
+        std::process::exit(1);
+    })
+}
 
-    loop {
-        let level = get_current_power(battery);
-        let current_threshold = find_lowest_threshold(level, &notified);
-        if let Some(threshold_val) = current_threshold {
-            if let Some(notification) = notified.get_mut(&threshold_val) {
-                if !notification.notified && level < last_battery_level {
-                    send_notification(&level, notification);
-                    notification.notified = true;
-                }
+fn show_config(args: &Args, effective: bool) {
+    let path = user_config_path(args);
+    if !effective {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => println!("{}", text.trim_end()),
+            Err(err) => {
+                eprintln!("Could not read {}, error: {}", path.to_string_lossy(), err);
+                std::process::exit(1);
             }
-            reset_other_notifications(&threshold_val, &mut notified);
         }
+        return;
+    }
 
-        check_notify_full_battery(&level, &last_battery_level, &mut config.full_notification);
+    for layer in config::layer_files(Some(&path)) {
+        eprintln!("Using config layer: {}", layer.to_string_lossy());
+    }
+    let config = config::load_effective_config(Some(&path)).unwrap_or_else(|err| {
+        eprintln!("Could not load config, error: {:?}", err);
+        std::process::exit(1);
+    });
+    match serde_json::to_string_pretty(&config) {
+        Ok(text) => println!("{}", text),
+        Err(err) => {
+            eprintln!("Could not serialize config, error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
 
-        last_battery_level = level;
+fn migrate_config(args: &Args, backup: bool) {
+    let path = user_config_path(args);
 
-        thread::sleep(sleep_time);
+    match config::migrate_config_file(&path, backup) {
+        Ok(config::MigrationOutcome::UpToDate) => {
+            println!(
+                "{} is up to date (version {})",
+                path.to_string_lossy(),
+                config::CURRENT_VERSION
+            );
+        }
+        Ok(config::MigrationOutcome::Migrated {
+            from_version,
+            backup,
+        }) => {
+            println!(
+                "Migrated {} from version {} to {}",
+                path.to_string_lossy(),
+                from_version,
+                config::CURRENT_VERSION
+            );
+            if let Some(backup) = backup {
+                println!("Backup of the old file: {}", backup.to_string_lossy());
+            }
+        }
+        Err(err) => {
+            eprintln!(
+                "Could not migrate config file {}, error: {:?}",
+                path.to_string_lossy(),
+                err
+            );
+            std::process::exit(1);
+        }
     }
 }
@@ -1,16 +1,34 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
 use notify_rust::Urgency as SendUrgency;
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::battery::CapacityLevel;
+use crate::ChargingStatus;
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug, Deserialize, Serialize)]
+#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub enum Urgency {
     /// The behaviour for `Low` urgency depends on the notification server.
     Low = 0,
     /// The behaviour for `Normal` urgency depends on the notification server.
+    #[default]
     Normal = 1,
     /// A critical notification will not time out.
     Critical = 2,
 }
 
+impl Urgency {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
 impl From<&Urgency> for SendUrgency {
     fn from(value: &Urgency) -> Self {
         match value {
@@ -21,9 +39,104 @@ impl From<&Urgency> for SendUrgency {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Command run when a notification is sent, either a command line or an object with options
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum CommandSpec {
+    Line(String),
+    Detailed {
+        // command line, split like a shell would but not run through a shell
+        run: String,
+        // working directory of the command
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cwd: Option<PathBuf>,
+        // additional environment variables
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        env: BTreeMap<String, String>,
+        // seconds after which the command is killed, overrides the hooks timeout
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u32>,
+        // seconds to wait before the command is started
+        #[serde(skip_serializing_if = "Option::is_none")]
+        delay_secs: Option<u32>,
+        // run the following commands even if this one fails
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        continue_on_error: bool,
+    },
+}
+
+impl CommandSpec {
+    pub fn run(&self) -> &str {
+        match self {
+            CommandSpec::Line(run) | CommandSpec::Detailed { run, .. } => run,
+        }
+    }
+
+    pub fn cwd(&self) -> Option<&Path> {
+        match self {
+            CommandSpec::Line(_) => None,
+            CommandSpec::Detailed { cwd, .. } => cwd.as_deref(),
+        }
+    }
+
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        static NO_ENV: BTreeMap<String, String> = BTreeMap::new();
+        match self {
+            CommandSpec::Line(_) => &NO_ENV,
+            CommandSpec::Detailed { env, .. } => env,
+        }
+    }
+
+    pub fn timeout_secs(&self) -> Option<u32> {
+        match self {
+            CommandSpec::Line(_) => None,
+            CommandSpec::Detailed { timeout_secs, .. } => *timeout_secs,
+        }
+    }
+
+    pub fn delay_secs(&self) -> Option<u32> {
+        match self {
+            CommandSpec::Line(_) => None,
+            CommandSpec::Detailed { delay_secs, .. } => *delay_secs,
+        }
+    }
+
+    pub fn continue_on_error(&self) -> bool {
+        match self {
+            CommandSpec::Line(_) => false,
+            CommandSpec::Detailed {
+                continue_on_error, ..
+            } => *continue_on_error,
+        }
+    }
+}
+
+/// Power management action executed through logind when a threshold is reached
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowerAction {
+    Hibernate,
+    Suspend,
+    Poweroff,
+    HybridSleep,
+}
+
+impl PowerAction {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            PowerAction::Hibernate => "hibernate",
+            PowerAction::Suspend => "suspend",
+            PowerAction::Poweroff => "poweroff",
+            PowerAction::HybridSleep => "hybrid-sleep",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Notification {
-    // threshold level for which a notification should be sent
+    // threshold level for which a notification should be sent, either in percent or the name of a
+    // capacity level such as "Low" for batteries that only report capacity_level
+    #[serde(deserialize_with = "deserialize_level")]
     pub level: u32,
     // urgency of the message, notification daemon can display them with different styling based on
     // the urgency
@@ -36,9 +149,9 @@ pub struct Notification {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub time_secs: Option<u32>,
 
-    // optional command/script that should should be run on notification
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub command: Option<String>,
+    // commands/scripts that should be run one after another on notification
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<CommandSpec>,
 
     // optional title to use for notification message
     // use {} for inserting percentage into template
@@ -49,8 +162,69 @@ pub struct Notification {
     // use {} for inserting percentage into template
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
+
+    // optional power management action such as hibernate, it is executed after a countdown
+    // which can be cancelled from the notification or by connecting AC
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<PowerAction>,
+
+    // seconds until the action is executed, defaults to DEFAULT_ACTION_COUNTDOWN_SECS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action_countdown_secs: Option<u32>,
+
+    // charging statuses for which the threshold is notified, all if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub statuses: Vec<ChargingStatus>,
 }
 
+impl Notification {
+    /// True if the threshold is notified while the battery has the given status
+    pub fn applies_to(&self, status: ChargingStatus) -> bool {
+        self.statuses.is_empty() || self.statuses.contains(&status)
+    }
+}
+
+/// Threshold level in the config: a percent, also written as string, or the name of a capacity
+/// level in any case which is stored as the percent it stands for
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Level {
+    Percent(u32),
+    Text(String),
+}
+
+impl Level {
+    fn percent(&self) -> Option<u32> {
+        match self {
+            Level::Percent(percent) => Some(*percent),
+            Level::Text(text) => text
+                .trim()
+                .parse()
+                .ok()
+                .or_else(|| CapacityLevel::from_name(text).map(CapacityLevel::percent)),
+        }
+    }
+}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let level = Level::deserialize(deserializer)?;
+    level
+        .percent()
+        .ok_or_else(|| D::Error::custom("expected a percent or a capacity level"))
+}
+
+/// Level of a notification in a raw config as percent, normalized like when deserializing it
+pub fn level_of(notification: &serde_json::Value) -> Option<u32> {
+    Level::deserialize(notification.get("level")?)
+        .ok()?
+        .percent()
+}
+
+pub const DEFAULT_ACTION_COUNTDOWN_SECS: u32 = 60;
+
+/// Message of the full notification when the battery stopped at a charge limit below 100%
+pub const DEFAULT_LIMIT_MESSAGE: &str = "Charged to {level}%, limit reached";
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BatteryFullNotification {
     pub urgency: Urgency,
@@ -62,9 +236,9 @@ pub struct BatteryFullNotification {
     // if disabled no notification is sent when battery is full
     pub enabled: bool,
 
-    // optional script to run on notification
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub command: Option<String>,
+    // scripts to run one after another on notification
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<CommandSpec>,
 
     // optional title to use for notification message
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -73,4 +247,8 @@ pub struct BatteryFullNotification {
     // optional template to use for notification message
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
+
+    // optional template used instead of message when the battery stopped at a charge limit below 100%
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_message: Option<String>,
 }
@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::notification::{PowerAction, Urgency};
+use crate::{get_status_charging, logind, send_message, Battery};
+
+const CANCEL_ACTION: &str = "cancel";
+
+/// Interval in which the countdown checks whether it was cancelled or AC got connected
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Run the power action after the countdown on a background thread. A notification with a cancel
+/// button is shown meanwhile and the action is aborted if the battery starts charging.
+pub fn start_countdown(
+    action: PowerAction,
+    countdown: Duration,
+    battery: Option<&Battery>,
+) -> thread::JoinHandle<()> {
+    let battery = battery.map(|battery| battery.to_owned());
+    thread::spawn(move || run_countdown(action, countdown, battery.as_deref()))
+}
+
+fn run_countdown(action: PowerAction, countdown: Duration, battery: Option<&Battery>) {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    show_countdown_notification(action, countdown, cancelled.clone());
+
+    let start = Instant::now();
+    while start.elapsed() < countdown {
+        thread::sleep(CHECK_INTERVAL);
+        if cancelled.load(Ordering::SeqCst) {
+            eprintln!("{} was cancelled by the user", action.as_str());
+            return;
+        }
+        if on_external_power(battery) {
+            eprintln!("{} was cancelled, AC is connected", action.as_str());
+            notify(
+                &format!("Cancelled {}", action.as_str()),
+                "The battery is charging",
+            );
+            return;
+        }
+    }
+
+    let result = zbus::blocking::Connection::system()
+        .and_then(|connection| logind::execute(&connection, action));
+    if let Err(err) = result {
+        eprintln!("Could not {}: {}", action.as_str(), err);
+        notify(&format!("Could not {}", action.as_str()), &err.to_string());
+    }
+}
+
+/// Show the countdown notification and wait for the cancel action on a separate thread
+fn show_countdown_notification(
+    action: PowerAction,
+    countdown: Duration,
+    cancelled: Arc<AtomicBool>,
+) {
+    let handle = notify_rust::Notification::new()
+        .summary("Critical Battery Status")
+        .body(&format!(
+            "Going to {} in {} seconds",
+            action.as_str(),
+            countdown.as_secs()
+        ))
+        .urgency(notify_rust::Urgency::Critical)
+        .action(CANCEL_ACTION, "Cancel")
+        .timeout(notify_rust::Timeout::Milliseconds(
+            countdown.as_millis() as u32
+        ))
+        .show();
+
+    match handle {
+        Ok(handle) => {
+            thread::spawn(move || {
+                handle.wait_for_action(|action| {
+                    if action == CANCEL_ACTION {
+                        cancelled.store(true, Ordering::SeqCst);
+                    }
+                })
+            });
+        }
+        Err(err) => eprintln!("Could not show countdown notification: {}", err),
+    }
+}
+
+fn on_external_power(battery: Option<&Battery>) -> bool {
+    get_status_charging(battery).is_ok_and(|status| status.is_plugged_in())
+}
+
+fn notify(title: &str, message: &str) {
+    if let Err(err) = send_message(title, message, &Urgency::Critical, None) {
+        eprintln!("{}", err);
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use crate::duration::format_duration;
+
+/// A period in which the battery was either discharging or plugged in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub plugged_in: bool,
+    pub start_level: u32,
+    // unix timestamp in seconds
+    pub started_at: u64,
+    // remaining energy at the start in Wh if the battery reports it
+    pub start_energy_wh: Option<f64>,
+}
+
+impl Session {
+    /// Summary of the session ending with the given level, None if the level did not change in the
+    /// expected direction, e.g. when the charger was only connected for a moment
+    pub fn summary(&self, level: u32, energy_wh: Option<f64>, now: u64) -> Option<String> {
+        let elapsed = now.saturating_sub(self.started_at);
+        let duration = format_duration(Duration::from_secs(elapsed));
+        if self.plugged_in {
+            if level <= self.start_level {
+                return None;
+            }
+            return Some(format!(
+                "Charged {}%→{}% in {}",
+                self.start_level, level, duration
+            ));
+        }
+
+        if level >= self.start_level {
+            return None;
+        }
+        let mut summary = format!("Used {}% over {}", self.start_level - level, duration);
+        let used_wh = self
+            .start_energy_wh
+            .zip(energy_wh)
+            .map(|(start, end)| start - end);
+        if let Some(used_wh) = used_wh.filter(|&used_wh| used_wh > 0.0 && elapsed > 0) {
+            let average_w = used_wh / (elapsed as f64 / 3600.0);
+            summary.push_str(&format!(" (avg {:.1} W)", average_w));
+        }
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summaries_of_discharge_and_charge() {
+        let discharge = Session {
+            plugged_in: false,
+            start_level: 90,
+            started_at: 0,
+            start_energy_wh: Some(50.0),
+        };
+        assert_eq!(
+            discharge.summary(47, Some(18.64), 11520).as_deref(),
+            Some("Used 43% over 3h12m (avg 9.8 W)")
+        );
+        assert_eq!(discharge.summary(90, None, 60), None);
+
+        let charge = Session {
+            plugged_in: true,
+            start_level: 20,
+            ..discharge
+        };
+        assert_eq!(
+            charge.summary(100, None, 3900).as_deref(),
+            Some("Charged 20%→100% in 1h05m")
+        );
+    }
+}
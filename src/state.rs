@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::{Battery, DEFAULT_BATTERY};
+
+/// A saved state older than this is ignored at startup, the battery level has likely changed a lot
+/// in the meantime, e.g. after a reboot on the next day
+pub const MAX_STATE_AGE: Duration = Duration::from_secs(15 * 60);
+
+/// Daemon state that survives restarts so thresholds are not notified again on every login
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    // unix timestamp in seconds of when the state was saved
+    pub saved_at: u64,
+    pub last_level: u32,
+    // thresholds that have already been notified together with the unix timestamp of the notification
+    pub notified: BTreeMap<u32, u64>,
+    pub full_notified: bool,
+    // the health notification is only sent once, so it is kept no matter how old the state is
+    #[serde(default)]
+    pub health_notified: bool,
+}
+
+/// Seconds since the unix epoch
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Directory for persistent daemon data, $XDG_STATE_HOME/powernotd or ~/.local/state/powernotd
+pub fn get_state_dir() -> Option<PathBuf> {
+    ProjectDirs::from("me", "laeri", "powernotd")
+        .and_then(|dir| dir.state_dir().map(|dir| dir.to_owned()))
+}
+
+/// The state is kept per battery as thresholds of different batteries are unrelated
+pub fn get_state_path(battery: Option<&Battery>) -> Option<PathBuf> {
+    let battery = battery.unwrap_or(DEFAULT_BATTERY);
+    get_state_dir().map(|dir| dir.join(format!("state-{}.json", battery)))
+}
+
+/// Load the state saved at path no matter how old it is
+pub fn load(path: &Path) -> Option<State> {
+    let text = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<State>(&text) {
+        Ok(state) => Some(state),
+        Err(err) => {
+            eprintln!(
+                "Ignoring state file {}, error: {}",
+                path.to_string_lossy(),
+                err
+            );
+            None
+        }
+    }
+}
+
+impl State {
+    /// True if the state is not older than MAX_STATE_AGE
+    pub fn is_recent(&self) -> bool {
+        now_secs().saturating_sub(self.saved_at) <= MAX_STATE_AGE.as_secs()
+    }
+}
+
+pub fn save(path: &Path, state: &State) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let text = serde_json::to_string(state)?;
+    // write to a temporary file first so a crash never leaves a truncated state behind
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, text)?;
+    std::fs::rename(tmp_path, path)
+}
@@ -0,0 +1,132 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::notification::Notification;
+use crate::ChargingStatus;
+
+/// Title and message of temperature notifications that do not set their own
+pub const DEFAULT_TITLE: &str = "Battery temperature";
+pub const DEFAULT_MESSAGE: &str = "Battery at {temperature} °C while {status}";
+
+/// Notifications about an overheating battery, the level of each notification is a temperature in °C
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TemperatureConfig {
+    #[serde(default, deserialize_with = "deserialize_notifications")]
+    pub notifications: Vec<Notification>,
+    // degrees the battery has to cool down below a threshold before it is notified again
+    #[serde(default = "default_hysteresis_c")]
+    pub hysteresis_c: u32,
+}
+
+fn default_hysteresis_c() -> u32 {
+    2
+}
+
+/// Notifications whose levels are plain numbers, names of capacity levels such as "Low" are no
+/// temperatures
+fn deserialize_notifications<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Notification>, D::Error> {
+    Vec::<Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|notification| {
+            if notification
+                .get("level")
+                .is_some_and(|level| !level.is_u64())
+            {
+                return Err(D::Error::custom(
+                    "temperature levels have to be numbers in °C",
+                ));
+            }
+            serde_json::from_value(notification).map_err(D::Error::custom)
+        })
+        .collect()
+}
+
+impl Default for TemperatureConfig {
+    fn default() -> Self {
+        TemperatureConfig {
+            notifications: vec![],
+            hysteresis_c: default_hysteresis_c(),
+        }
+    }
+}
+
+impl TemperatureConfig {
+    /// Reset the thresholds the battery cooled down from and return the hottest reached threshold
+    /// if it was not notified yet. The cooler reached thresholds are marked as notified with it so
+    /// they are not notified while the battery cools down.
+    pub fn check(&mut self, temperature_c: f64, status: ChargingStatus) -> Option<&Notification> {
+        for notification in &mut self.notifications {
+            if temperature_c < notification.level.saturating_sub(self.hysteresis_c) as f64 {
+                notification.notified = false;
+            }
+        }
+
+        let reached = |notification: &Notification| {
+            temperature_c >= notification.level as f64 && notification.applies_to(status)
+        };
+        let hottest = self
+            .notifications
+            .iter()
+            .filter(|notification| reached(notification))
+            .max_by_key(|notification| notification.level)?;
+        if hottest.notified {
+            return None;
+        }
+        let hottest_level = hottest.level;
+        for notification in &mut self.notifications {
+            if reached(notification) {
+                notification.notified = true;
+            }
+        }
+        self.notifications
+            .iter()
+            .find(|notification| notification.level == hottest_level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifies_hottest_threshold_until_cooled_down() {
+        let threshold = |level| Notification {
+            level,
+            ..Default::default()
+        };
+        let mut config = TemperatureConfig {
+            notifications: vec![threshold(45), threshold(50)],
+            ..Default::default()
+        };
+        let charging = ChargingStatus::Charging;
+        let notified = |config: &mut TemperatureConfig, temperature| {
+            config
+                .check(temperature, charging)
+                .map(|notification| notification.level)
+        };
+
+        assert_eq!(notified(&mut config, 40.0), None);
+        assert_eq!(notified(&mut config, 51.0), Some(50));
+        assert_eq!(notified(&mut config, 49.0), None);
+        // cooling below 48 °C resets the 50 °C threshold, but 45 °C stays notified
+        assert_eq!(notified(&mut config, 47.5), None);
+        assert_eq!(notified(&mut config, 50.0), Some(50));
+    }
+
+    #[test]
+    fn levels_have_to_be_numbers() {
+        let config = |level| {
+            serde_json::from_value::<TemperatureConfig>(serde_json::json!({
+                "notifications": [{ "level": level, "urgency": "Critical" }]
+            }))
+        };
+        assert_eq!(
+            config(serde_json::json!(45)).unwrap().notifications[0].level,
+            45
+        );
+        assert!(config(serde_json::json!("High")).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use crate::battery::Snapshot;
+use crate::duration::format_duration;
+
+/// Replace placeholders such as '{level}' or '{time_remaining}' with the values of the snapshot.
+/// '{}' is the level as before named placeholders existed, values that are not known become empty
+/// and unknown placeholders are kept as they are.
+pub fn render(template: &str, snapshot: &Snapshot) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder
+            .find('}')
+            .and_then(|end| Some((value(&placeholder[1..end], snapshot)?, end)));
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(&value);
+                rest = &placeholder[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn value(name: &str, snapshot: &Snapshot) -> Option<String> {
+    let value = match name {
+        "" | "level" => Some(snapshot.level.to_string()),
+        "status" => Some(snapshot.status.to_string()),
+        "battery" => Some(snapshot.battery.clone()),
+        "energy" => snapshot.energy_wh.map(|energy| format!("{:.1}", energy)),
+        "power" => snapshot.power_w.map(|power| format!("{:.1}", power)),
+        "time_remaining" => snapshot
+            .time_remaining_secs
+            .map(|secs| format_duration(Duration::from_secs(secs))),
+        "health" => snapshot.health_percent.map(|health| health.to_string()),
+        "temperature" => snapshot
+            .temperature_c
+            .map(|temperature| format!("{:.0}", temperature)),
+        "threshold" => snapshot.active_threshold.map(|level| level.to_string()),
+        _ => return None,
+    };
+    Some(value.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChargingStatus;
+
+    #[test]
+    fn render_replaces_known_placeholders() {
+        let snapshot = Snapshot {
+            level: 54,
+            status: ChargingStatus::Discharging,
+            battery: "BAT0".to_string(),
+            energy_wh: Some(30.26),
+            power_w: None,
+            time_remaining_secs: Some(11520),
+            health_percent: Some(87),
+            temperature_c: None,
+            active_threshold: None,
+        };
+        assert_eq!(
+            render("{level}% {status} {time_remaining}", &snapshot),
+            "54% discharging 3h12m"
+        );
+        assert_eq!(
+            render("{}% {energy}Wh {power}W {unknown} {", &snapshot),
+            "54% 30.3Wh W {unknown} {"
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Empty directory below the system temp dir for the files of a test, removed again when dropped
+/// so it is cleaned up even if the test fails
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// The name has to be unique among the tests of the crate as they run in parallel
+    pub fn new(name: &str) -> TestDir {
+        let path = std::env::temp_dir().join(format!("powernotd-{}-{}", name, std::process::id()));
+        // left over if a previous run with the same pid was killed
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
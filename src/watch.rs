@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::battery::{self, get_time_remaining};
+use crate::control::{self, Response};
+use crate::daemon::DaemonStatus;
+use crate::duration::format_duration;
+use crate::notification::Urgency;
+use crate::*;
+
+mod format;
+
+pub use format::Format;
+
+/// Interval in which sysfs is read when no daemon is running
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Print a line whenever the level or charging status changes. The status is received from the
+/// running daemon and read from sysfs while no daemon is running. Never returns unless stdout is
+/// closed.
+pub fn run(format: Format, battery: Option<&Battery>, thresholds: &HashMap<u32, Urgency>) {
+    // the time remaining changes on every read, so it alone does not cause a new line
+    let mut last_printed = None;
+    let mut print = |status: &DaemonStatus| {
+        let urgency = status
+            .active_threshold
+            .and_then(|threshold_val| thresholds.get(&threshold_val));
+        let printed = (status.level, status.status, urgency.copied());
+        if last_printed.as_ref() == Some(&printed) {
+            return true;
+        }
+        last_printed = Some(printed);
+        let mut stdout = std::io::stdout();
+        writeln!(stdout, "{}", format_line(format, status, urgency))
+            .and_then(|_| stdout.flush())
+            .is_ok()
+    };
+
+    loop {
+        // the daemon pushes every change, polling is only needed once it is gone
+        if let Ok(subscription) = subscribe(battery) {
+            for status in subscription {
+                if !print(&status) {
+                    return;
+                }
+            }
+        }
+
+        match read_status(battery, thresholds) {
+            Ok(status) => {
+                if !print(&status) {
+                    return;
+                }
+            }
+            Err(err) => eprintln!("{}", err),
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Status updates of the running daemon, ends when the daemon exits. Fails if no daemon is running
+/// or it watches another battery.
+fn subscribe(battery: Option<&Battery>) -> std::io::Result<impl Iterator<Item = DaemonStatus>> {
+    let mut stream = control::connect()?;
+    stream.write_all(b"subscribe\n")?;
+    let mut lines = BufReader::new(stream)
+        .lines()
+        .map_while(Result::ok)
+        .peekable();
+
+    let battery = battery.unwrap_or(DEFAULT_BATTERY).to_owned();
+    let first = lines
+        .peek()
+        .and_then(|line| serde_json::from_str::<Response>(line).ok())
+        .and_then(|response| response.status);
+    if first.is_none_or(|status| status.battery != battery) {
+        return Err(std::io::Error::other("daemon watches another battery"));
+    }
+    Ok(lines.map_while(|line| serde_json::from_str::<Response>(&line).ok()?.status))
+}
+
+fn read_status(
+    battery: Option<&Battery>,
+    thresholds: &HashMap<u32, Urgency>,
+) -> Result<DaemonStatus, Error> {
+    let level = get_current_power(battery)?;
+    let status = get_status_charging(battery)?;
+    let time_remaining = get_time_remaining(
+        &battery::get_battery_dir(battery),
+        status == ChargingStatus::Charging,
+    );
+    let active_threshold = find_lowest_threshold(level, thresholds);
+    Ok(DaemonStatus {
+        level,
+        status,
+        battery: battery.unwrap_or(DEFAULT_BATTERY).to_string(),
+        active_threshold,
+        snoozed_until: None,
+        time_remaining_secs: time_remaining.map(|remaining| remaining.as_secs()),
+    })
+}
+
+/// Render the status as a single line, urgency is the one of the active threshold
+pub fn format_line(format: Format, status: &DaemonStatus, urgency: Option<&Urgency>) -> String {
+    let text = format!("{}%", status.level);
+    let time_remaining = status.time_remaining_secs.map(|secs| {
+        let until = if status.status == ChargingStatus::Charging {
+            "until full"
+        } else {
+            "remaining"
+        };
+        format!("{} {}", format_duration(Duration::from_secs(secs)), until)
+    });
+    let tooltip = match &time_remaining {
+        Some(time_remaining) => format!("{}, {}", status.status, time_remaining),
+        None => status.status.to_string(),
+    };
+
+    match format {
+        Format::Waybar => json!({
+            "text": text,
+            "tooltip": tooltip,
+            "class": urgency.map_or("", Urgency::as_str),
+            "percentage": status.level,
+        })
+        .to_string(),
+        Format::I3bar => json!({
+            "full_text": format!("{} {}", text, status.status),
+            "short_text": text,
+            "urgent": urgency == Some(&Urgency::Critical),
+        })
+        .to_string(),
+        Format::Polybar if status.status == ChargingStatus::Discharging => text,
+        Format::Polybar => format!("{} ({})", text, status.status),
+        Format::Plain => format!("{} {}", text, status.status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waybar_line_has_class_of_threshold_urgency() {
+        let status = DaemonStatus {
+            level: 12,
+            status: ChargingStatus::Discharging,
+            battery: "BAT0".to_string(),
+            active_threshold: Some(15),
+            snoozed_until: None,
+            time_remaining_secs: Some(2700),
+        };
+        assert_eq!(
+            format_line(Format::Waybar, &status, Some(&Urgency::Critical)),
+            r#"{"text":"12%","tooltip":"discharging, 45m remaining","class":"critical","percentage":12}"#
+        );
+        assert_eq!(format_line(Format::Plain, &status, None), "12% discharging");
+    }
+}
@@ -0,0 +1,14 @@
+// included by build.rs for the man page and shell completions, so only clap can be used here
+
+/// Output format of the watch command
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// json with 'text', 'tooltip', 'class' and 'percentage' for waybar's 'return-type': 'json'
+    Waybar,
+    /// json block with 'full_text', 'short_text' and 'urgent' for i3blocks' 'format=json'
+    I3bar,
+    /// text such as '54%', or '54% (charging)' when not discharging
+    Polybar,
+    /// text such as '54% discharging'
+    Plain,
+}